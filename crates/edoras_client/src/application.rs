@@ -1,26 +1,79 @@
-use anyhow::Result as AnyResult;
+use crate::transport::Transport;
+use anyhow::{bail, Context, Result as AnyResult};
 use async_std::channel::{Receiver, Sender};
-use async_std::net::TcpStream;
 use async_std::sync::{Mutex, RwLock};
-use async_std::task;
-use edoras_core::{Message, MessageBuilder, MessageType, HOST, PORT};
+use async_std::{future, task};
+#[cfg(feature = "tls")]
+use edoras_core::tls::TlsTrust;
+use edoras_core::{
+    Capabilities, CaptureSide, CaptureWriter, Compression, Direction, Disconnect, DisconnectReason,
+    Encoding, Endpoint, ErrorReply, Frame, FrameScheduler, Hello, Keepalive, Message,
+    MessageBuilder, MessageDecoder, MessageEncoder, MessageError, MessageType, Payload, Pong,
+    RegisterRequest, RequestId, Welcome,
+};
+use futures::channel::oneshot;
+use futures::{pin_mut, select, FutureExt};
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 const TRACING_LEVEL: tracing::Level = tracing::Level::DEBUG;
 
+const SOFTWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+const CAPABILITIES: Capabilities = Capabilities::CHECKSUM
+    .union(Capabilities::DEFLATE)
+    .union(Capabilities::ZSTD)
+    .union(Capabilities::CHANNELS);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// how long the server may take to acknowledge a Disconnect
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// the connection is closed if the server does not answer a Ping within this time
+const PONG_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// endpoint of the server if none is given on the command line
+const ADDRESS_VAR: &str = "EDORAS_ADDRESS";
+
+/// file the frames of the connection are captured to, capturing is off if it is not set
+const CAPTURE_VAR: &str = "EDORAS_CAPTURE";
+
+#[allow(dead_code)]
 pub(crate) struct AppData {
     send: HashMap<String, Message>, // user_from -> message
     recv: HashMap<String, Message>, // user_to -> message
+
+    pending: HashMap<RequestId, oneshot::Sender<Message>>, // request_id -> waiting request
+    rtt: Option<Duration>,
 }
 
 pub(crate) struct Stream {
-    stream: Option<TcpStream>,
+    stream: Option<Transport>,
+    decoder: MessageDecoder,
+    encoder: MessageEncoder,
+    capture: Option<CaptureWriter<BufWriter<File>>>,
+    keepalive: Keepalive,
+    scheduler: FrameScheduler, // queued messages of the app
+}
+
+enum StreamEvent {
+    Incoming(Result<Message, MessageError>),
+    Outgoing(Message),
+    Writable, // a queued frame can be written
+    HealthCheck,
+    Closed,
 }
 
 pub(crate) struct App {
     stream: Arc<Mutex<Stream>>,
     data: Arc<RwLock<AppData>>,
+
+    tx: Sender<Message>, // channel for telling the stream handler to send a message
+    rx: Receiver<Message>,
+    next_request_id: AtomicU32,
 }
 
 impl AppData {
@@ -28,28 +81,145 @@ impl AppData {
         Self {
             send: HashMap::new(),
             recv: HashMap::new(),
+
+            pending: HashMap::new(),
+            rtt: None,
         }
     }
+
+    /// round trip time to the server of the last answered Ping
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    pub fn set_rtt(&mut self, rtt: Duration) {
+        self.rtt = Some(rtt);
+    }
+
+    pub fn insert_pending(&mut self, request_id: RequestId, waiter: oneshot::Sender<Message>) {
+        self.pending.insert(request_id, waiter);
+    }
+
+    pub fn remove_pending(&mut self, request_id: RequestId) -> Option<oneshot::Sender<Message>> {
+        self.pending.remove(&request_id)
+    }
+
+    pub fn clear_pending(&mut self) {
+        self.pending.clear();
+    }
 }
 
 impl Stream {
     pub fn new() -> Self {
         Self {
             stream: None,
+            decoder: MessageDecoder::new(),
+            encoder: MessageEncoder::new(),
+            capture: None,
+            keepalive: Keepalive::new(PONG_TIMEOUT),
+            scheduler: FrameScheduler::new(),
         }
     }
 
-    pub async fn set_stream(&mut self, stream: TcpStream) {
-        self.stream = Some(stream);
+    /// records every frame that is sent or received from now on
+    pub fn set_capture(&mut self, capture: CaptureWriter<BufWriter<File>>) {
+        self.capture = Some(capture);
     }
 
-    pub fn stream(&self) -> &TcpStream {
-        self.stream.as_ref().unwrap()
+    pub async fn set_stream(&mut self, stream: Transport) {
+        self.stream = Some(stream);
     }
 
-    pub fn stream_mut(&mut self) -> &mut TcpStream {
+    pub fn stream_mut(&mut self) -> &mut Transport {
         self.stream.as_mut().unwrap()
     }
+
+    /// writes the message right away, ahead of the queued ones
+    pub async fn send(&mut self, message: &Message) -> Result<(), MessageError> {
+        let frame = self.encoder.encode(message);
+        self.send_frame(frame).await
+    }
+
+    /// queues the message behind the messages of its channel, see [`Stream::send_next`]
+    pub fn queue(&mut self, message: &Message) {
+        let frame = self.encoder.encode(message);
+        self.scheduler.push(message, frame);
+    }
+
+    pub fn has_queued(&self) -> bool {
+        !self.scheduler.is_empty()
+    }
+
+    /// writes the next queued frame, the channels take turns, so a bulk transfer does not hold
+    /// up the chat
+    pub async fn send_next(&mut self) -> Result<(), MessageError> {
+        match self.scheduler.pop() {
+            Some(frame) => self.send_frame(frame).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn send_frame(&mut self, frame: Frame) -> Result<(), MessageError> {
+        if self.capture.is_some() {
+            self.capture(Direction::Outgoing, &frame.to_bytes());
+        }
+        frame.send(self.stream_mut()).await
+    }
+
+    pub async fn recv(&mut self) -> Result<Message, MessageError> {
+        let message = self.decoder.recv(self.stream.as_mut().unwrap()).await;
+        if let Some(frame) = self.decoder.take_last_frame() {
+            self.capture(Direction::Incoming, &frame);
+        }
+        message
+    }
+
+    /// a capture that fails to write is dropped, the connection goes on without it
+    fn capture(&mut self, direction: Direction, frame: &[u8]) {
+        let Some(capture) = self.capture.as_mut() else {
+            return;
+        };
+
+        if let Err(e) = capture.record(direction, frame) {
+            tracing::warn!("Failed to capture frame: {}", e);
+            self.capture = None;
+        }
+    }
+
+    /// sends a Ping that has to be answered within the pong timeout
+    pub async fn ping(&mut self) -> Result<(), MessageError> {
+        let ping = self.keepalive.ping();
+        self.send(&ping.to_message()).await
+    }
+
+    /// sends the Hello and waits for the servers Welcome
+    pub async fn handshake(&mut self) -> AnyResult<Welcome> {
+        self.send(&Hello::new(SOFTWARE_VERSION, CAPABILITIES).to_message())
+            .await?;
+
+        let msg = self.recv().await?;
+        if msg.mtype() == MessageType::Error {
+            bail!(
+                "Server rejected handshake: {}",
+                ErrorReply::from_message(&msg)?
+            );
+        }
+
+        let welcome = Welcome::from_message(&msg)?;
+        let checksum = welcome.capabilities().contains(Capabilities::CHECKSUM);
+        self.encoder.set_checksum(checksum);
+        self.decoder.set_require_checksum(checksum);
+        self.encoder
+            .set_channels(welcome.capabilities().contains(Capabilities::CHANNELS));
+        self.encoder
+            .set_encoding(Encoding::from_version(welcome.protocol_version()));
+
+        let compression = Compression::from_capabilities(welcome.capabilities());
+        self.encoder.set_compression(compression);
+        self.decoder.set_compression(compression);
+
+        Ok(welcome)
+    }
 }
 
 impl App {
@@ -59,50 +229,118 @@ impl App {
             .compact()
             .init();
 
+        let (tx, rx) = async_std::channel::unbounded();
+
         Self {
             stream: Arc::new(Mutex::new(Stream::new())),
             data: Arc::new(RwLock::new(AppData::new())),
+
+            tx,
+            rx,
+            next_request_id: AtomicU32::new(0),
+        }
+    }
+
+    /// endpoint from the command line, the environment or the default one
+    fn endpoint() -> AnyResult<Endpoint> {
+        let address = std::env::args()
+            .nth(1)
+            .or_else(|| std::env::var(ADDRESS_VAR).ok());
+
+        match address {
+            Some(address) => address
+                .parse()
+                .with_context(|| format!("Invalid endpoint {}", address)),
+            None => Ok(Endpoint::default()),
+        }
+    }
+
+    async fn connect(endpoint: &Endpoint) -> AnyResult<Transport> {
+        tracing::debug!("Connecting to {}", endpoint);
+
+        let (address, transport) = match endpoint {
+            Endpoint::Tcp(address) | Endpoint::WebSocket(address) => (
+                address,
+                Transport::connect(&address.host, address.port).await?,
+            ),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => return Ok(Transport::connect_unix(path).await?),
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => bail!("{} needs unix socket support", endpoint),
+        };
+
+        #[cfg(feature = "tls")]
+        let transport = match address.tls {
+            true => transport.tls(&address.host, &TlsTrust::from_env()?).await?,
+            false => transport,
+        };
+        #[cfg(not(feature = "tls"))]
+        if address.tls {
+            bail!("{} needs TLS support", endpoint);
         }
+
+        #[cfg(feature = "ws")]
+        if endpoint.is_websocket() {
+            return Ok(transport.websocket(&format!("{}/", endpoint)).await?);
+        }
+        #[cfg(not(feature = "ws"))]
+        if endpoint.is_websocket() {
+            bail!("{} needs WebSocket support", endpoint);
+        }
+
+        Ok(transport)
     }
 
     pub async fn run(&mut self) -> AnyResult<()> {
         tracing::debug!("Starting application");
-        tracing::debug!("Connecting to {}:{}", HOST, PORT);
-        // self.data
-        //     .set_stream(TcpStream::connect((HOST, PORT)).await?);
+        let transport = Self::connect(&Self::endpoint()?).await?;
+
         let mut stream = self.stream.lock().await;
-        stream
-            .set_stream(TcpStream::connect((HOST, PORT)).await?)
-            .await;
+        stream.set_stream(transport).await;
+        if let Some(path) = std::env::var_os(CAPTURE_VAR) {
+            let capture = CaptureWriter::create(&path, CaptureSide::Client)
+                .with_context(|| format!("Failed to create capture {:?}", path))?;
+            stream.set_capture(capture);
+        }
+
+        let welcome = stream.handshake().await?;
+        tracing::info!(
+            "Connected to server v{} | protocol v{}, capabilities {:?}",
+            welcome.software_version(),
+            welcome.protocol_version(),
+            welcome.capabilities()
+        );
         drop(stream);
 
         tracing::debug!("Application started");
 
-        let (tx, rx): (Sender<Message>, Receiver<Message>) = async_std::channel::unbounded(); // channel for telling the stream handler to send a message
-
         let handler = task::spawn(Self::stream_handler(
             self.stream.clone(),
             self.data.clone(),
-            rx,
+            self.rx.clone(),
         ));
 
-        if let Err(e) = tx
-            .send(
-                MessageBuilder::new()
-                    .with_type(MessageType::Register)
-                    .with_field("luffy")
-                    .build(),
-            )
-            .await
-        {
-            tracing::error!("Failed to send message to channel: {}", e);
+        let register = RegisterRequest {
+            username: String::from("luffy"),
+        };
+
+        match self.request(register.to_builder()).await {
+            Ok(reply) if reply.mtype() == MessageType::Okay => {
+                tracing::info!("Registered as luffy")
+            }
+            Ok(reply) => match ErrorReply::from_message(&reply) {
+                Ok(error) => tracing::error!("Failed to register: {}", error),
+                Err(_) => tracing::error!("Unexpected reply to register | {:?}", reply.mtype()),
+            },
+            Err(e) => tracing::error!("Failed to register: {}", e),
         }
 
         loop {
             let mut input = String::new();
             std::io::stdin().read_line(&mut input)?;
 
-            if let Err(e) = tx.send(Message::DISCONNECT_MESSAGE).await {
+            let disconnect = Disconnect::new(DisconnectReason::ClientQuit, "").to_message();
+            if let Err(e) = self.tx.send(disconnect).await {
                 tracing::error!("Failed to send message to channel: {}", e);
             } else {
                 break;
@@ -110,41 +348,180 @@ impl App {
         }
 
         handler.await;
+        tracing::debug!(
+            "Disconnected | last round trip {:?}",
+            self.data.read().await.rtt()
+        );
 
         Ok(())
     }
 
+    /// sends the message with a new request id and waits for the reply carrying the same id
+    pub async fn request(&self, message: MessageBuilder) -> AnyResult<Message> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (waiter, reply) = oneshot::channel();
+        self.data.write().await.insert_pending(request_id, waiter);
+
+        self.tx
+            .send(message.with_request_id(request_id).build())
+            .await?;
+
+        match future::timeout(REQUEST_TIMEOUT, reply).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(_)) => bail!(
+                "Connection closed before request {} was answered",
+                request_id
+            ),
+            Err(_) => {
+                self.data.write().await.remove_pending(request_id);
+                bail!("Request {} timed out", request_id)
+            }
+        }
+    }
+
     pub async fn stream_handler(
         stream: Arc<Mutex<Stream>>,
         appdata: Arc<RwLock<AppData>>,
         rx: Receiver<Message>,
     ) {
         let mut stream = stream.lock().await;
+        let mut next_check = Instant::now() + HEALTH_CHECK_INTERVAL;
+        let mut closing = None; // deadline for the acknowledgement of our Disconnect
 
         loop {
-            if let Ok(msg) = rx.recv().await {
-                if let Err(e) = msg.send(stream.stream_mut()).await {
-                    tracing::error!("Failed to send message: {}", e);
+            let event = {
+                // a single frame is written per turn, so the messages that are sent in the
+                // meantime are still scheduled and incoming ones are not held up
+                let writable = match stream.has_queued() {
+                    true => future::ready(()).left_future(),
+                    false => future::pending().right_future(),
+                };
+                let incoming = stream.recv().fuse();
+                let outgoing = rx.recv().fuse();
+                let wake = closing.unwrap_or(next_check);
+                let check = task::sleep(wake.saturating_duration_since(Instant::now())).fuse();
+                let writable = writable.fuse();
+                pin_mut!(incoming, outgoing, check, writable);
+
+                select! {
+                    msg = incoming => StreamEvent::Incoming(msg),
+                    msg = outgoing => match msg {
+                        Ok(msg) => StreamEvent::Outgoing(msg),
+                        Err(_) => StreamEvent::Closed,
+                    },
+                    () = writable => StreamEvent::Writable,
+                    () = check => StreamEvent::HealthCheck,
                 }
-                if msg.mtype() == MessageType::Disconnect {
-                    break;
+            };
+
+            match event {
+                StreamEvent::HealthCheck => {
+                    if closing.is_some() {
+                        tracing::debug!("Disconnect was not acknowledged by the server");
+                        break;
+                    }
+                    next_check = Instant::now() + HEALTH_CHECK_INTERVAL;
+
+                    if stream.keepalive.timed_out() {
+                        tracing::error!("No Pong from server within {:?}", PONG_TIMEOUT);
+                        let detail = format!("No Pong within {:?}", PONG_TIMEOUT);
+                        let disconnect = Disconnect::new(DisconnectReason::IdleTimeout, detail);
+                        // the server is most likely gone, so there is no point in waiting for it
+                        let _ = stream.send(&disconnect.to_message()).await;
+                        break;
+                    }
+                    if let Err(e) = stream.ping().await {
+                        tracing::error!("Failed to send Ping: {}", e);
+                    }
                 }
-                continue;
-            }
+                StreamEvent::Outgoing(msg) => {
+                    stream.queue(&msg);
+                    // a Disconnect skips the queued messages, so it is written next
+                    if msg.mtype() == MessageType::Disconnect {
+                        closing = Some(Instant::now() + DISCONNECT_TIMEOUT);
+                    }
+                }
+                StreamEvent::Writable => {
+                    if let Err(e) = stream.send_next().await {
+                        tracing::error!("Failed to send message: {}", e);
+                    }
+                }
+                StreamEvent::Incoming(Ok(msg)) => {
+                    let waiter = match msg.request_id() {
+                        Some(request_id) => appdata.write().await.remove_pending(request_id),
+                        None => None,
+                    };
 
-            if !Message::peek_for_header(stream.stream_mut()).await {
-                continue;
-            }
+                    if let Some(waiter) = waiter {
+                        // the request may have timed out in the meantime
+                        let _ = waiter.send(msg);
+                        continue;
+                    }
+
+                    if let Ok(error) = ErrorReply::from_message(&msg) {
+                        tracing::error!("Server reported an error: {}", error);
+                        continue;
+                    }
 
-            let msg = match Message::recv(stream.stream_mut()).await {
-                Ok(msg) => msg,
-                Err(e) => {
+                    match msg.mtype() {
+                        MessageType::Disconnect if closing.is_some() => {
+                            tracing::debug!("Disconnect acknowledged by the server");
+                            break;
+                        }
+                        MessageType::Disconnect => {
+                            match Disconnect::from_message(&msg) {
+                                Ok(disconnect) => {
+                                    tracing::info!("Disconnected by server: {}", disconnect)
+                                }
+                                Err(_) => tracing::info!("Disconnected by server"),
+                            }
+                            // the echo acknowledges the Disconnect
+                            if let Err(e) = stream.send(&msg).await {
+                                tracing::error!("Failed to acknowledge Disconnect: {}", e);
+                            }
+                            break;
+                        }
+                        MessageType::Ping => {
+                            // the fields are echoed as they are
+                            let pong = MessageBuilder::new()
+                                .with_type(MessageType::Pong)
+                                .with_reply_to(&msg)
+                                .with_fields(msg.data())
+                                .build();
+                            if let Err(e) = stream.send(&pong).await {
+                                tracing::error!("Failed to answer Ping: {}", e);
+                            }
+                        }
+                        MessageType::Pong => {
+                            let rtt = Pong::from_message(&msg)
+                                .ok()
+                                .and_then(|pong| stream.keepalive.pong(&pong));
+                            if let Some(rtt) = rtt {
+                                tracing::debug!("Round trip to server | {:?}", rtt);
+                                appdata.write().await.set_rtt(rtt);
+                            }
+                        }
+                        // TODO: handle message
+                        mtype => tracing::debug!("Received message | {:?}", mtype),
+                    }
+                }
+                StreamEvent::Incoming(Err(MessageError::ConnectionClosed)) => {
+                    tracing::info!("Connection closed by server");
+                    break;
+                }
+                StreamEvent::Incoming(Err(MessageError::ReadError(e))) => {
+                    // every following read would fail right away as well
+                    tracing::error!("Failed to read from server: {}", e);
+                    break;
+                }
+                StreamEvent::Incoming(Err(e)) => {
                     tracing::error!("Failed to receive message: {}", e);
-                    continue;
                 }
-            };
-
-            // TODO: handle message
+                StreamEvent::Closed => break,
+            }
         }
+
+        // wakes up all requests that are still waiting for a reply
+        appdata.write().await.clear_pending();
     }
 }
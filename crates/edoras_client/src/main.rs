@@ -1,4 +1,5 @@
 mod application;
+mod transport;
 
 #[async_std::main]
 async fn main() {
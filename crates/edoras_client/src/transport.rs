@@ -0,0 +1,129 @@
+use async_std::net::TcpStream;
+#[cfg(unix)]
+use async_std::os::unix::net::UnixStream;
+#[cfg(feature = "tls")]
+use edoras_core::tls::{self, client::TlsStream, TlsTrust};
+#[cfg(feature = "ws")]
+use edoras_core::WsStream;
+use futures::{AsyncRead, AsyncWrite};
+use std::io::{self, IoSlice};
+#[cfg(unix)]
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// the connection to the server
+#[derive(Debug)]
+pub(crate) enum Transport {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsStream<Transport>>),
+    #[cfg(feature = "ws")]
+    Ws(Box<WsStream<Transport>>),
+}
+
+// IMPLEMENTATION
+
+impl Transport {
+    pub async fn connect(host: &str, port: u16) -> io::Result<Self> {
+        Ok(Self::Tcp(TcpStream::connect((host, port)).await?))
+    }
+
+    #[cfg(unix)]
+    pub async fn connect_unix(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::Unix(UnixStream::connect(path.as_ref()).await?))
+    }
+
+    /// runs the TLS handshake on the connection, the certificate has to be valid for the host
+    #[cfg(feature = "tls")]
+    pub async fn tls(self, host: &str, trust: &TlsTrust) -> io::Result<Self> {
+        let stream = tls::connector(trust)?
+            .connect(tls::server_name(host)?, self)
+            .await?;
+        Ok(Self::Tls(Box::new(stream)))
+    }
+
+    /// runs the WebSocket handshake on the connection, frames are carried in binary messages
+    #[cfg(feature = "ws")]
+    pub async fn websocket(self, url: &str) -> io::Result<Self> {
+        Ok(Self::Ws(Box::new(WsStream::connect(url, self).await?)))
+    }
+}
+
+impl AsyncRead for Transport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+            #[cfg(feature = "ws")]
+            Self::Ws(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Transport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+            #[cfg(feature = "ws")]
+            Self::Ws(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_write_vectored(cx, bufs),
+            #[cfg(feature = "ws")]
+            Self::Ws(stream) => Pin::new(stream.as_mut()).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+            #[cfg(feature = "ws")]
+            Self::Ws(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_close(cx),
+            #[cfg(unix)]
+            Self::Unix(stream) => Pin::new(stream).poll_close(cx),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_close(cx),
+            #[cfg(feature = "ws")]
+            Self::Ws(stream) => Pin::new(stream.as_mut()).poll_close(cx),
+        }
+    }
+}
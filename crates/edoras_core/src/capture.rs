@@ -0,0 +1,187 @@
+use bytes::Bytes;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// start of every capture file, the last byte is the version of the format
+pub const CAPTURE_MAGIC: [u8; 6] = *b"EDCAP\x01";
+
+/// file extension of capture files
+pub const CAPTURE_EXTENSION: &str = "edcap";
+
+const SIDE_SIZE: usize = size_of::<u8>();
+const TIMESTAMP_SIZE: usize = size_of::<u64>();
+const DIRECTION_SIZE: usize = size_of::<u8>();
+const LENGTH_SIZE: usize = size_of::<u32>();
+
+/// side of the connection that wrote a capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CaptureSide {
+    Client = 0,
+    Server = 1,
+}
+
+/// direction of a frame, seen from the side that wrote the capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Incoming = 0,
+    Outgoing = 1,
+}
+
+/// a frame as it was sent or received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    pub timestamp: Duration, // since the unix epoch
+    pub direction: Direction,
+    pub frame: Bytes,
+}
+
+/// writes every frame of a connection to a capture
+///
+/// ```text
+/// CAPTURE_MAGIC | side u8 | (timestamp_us u64 | direction u8 | length u32 | frame)*
+/// ```
+///
+/// all integers are little endian, every record is flushed so a capture survives a crash
+#[derive(Debug)]
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+}
+
+/// reads the records of a capture in the order they were written
+#[derive(Debug)]
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    side: CaptureSide,
+}
+
+// IMPLEMENTATION
+
+impl CaptureSide {
+    /// direction of the frames the client sent, e.g. to replay them against a server
+    pub fn client_frames(self) -> Direction {
+        match self {
+            Self::Client => Direction::Outgoing,
+            Self::Server => Direction::Incoming,
+        }
+    }
+
+    /// direction of the frames the server sent, e.g. to replay them against a client
+    pub fn server_frames(self) -> Direction {
+        match self {
+            Self::Client => Direction::Incoming,
+            Self::Server => Direction::Outgoing,
+        }
+    }
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    /// creates the capture file, an existing file is overwritten
+    pub fn create(path: impl AsRef<Path>, side: CaptureSide) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), side)
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut writer: W, side: CaptureSide) -> io::Result<Self> {
+        writer.write_all(&CAPTURE_MAGIC)?;
+        writer.write_all(&[side as u8])?;
+        writer.flush()?;
+
+        Ok(Self {
+            writer,
+        })
+    }
+
+    /// writes the frame with the current time
+    pub fn record(&mut self, direction: Direction, frame: &[u8]) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let length = u32::try_from(frame.len()).map_err(|_| ErrorKind::InvalidInput)?;
+
+        self.writer
+            .write_all(&(timestamp.as_micros() as u64).to_le_bytes())?;
+        self.writer.write_all(&[direction as u8])?;
+        self.writer.write_all(&length.to_le_bytes())?;
+        self.writer.write_all(frame)?;
+        self.writer.flush()
+    }
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// reads the header of the capture
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; CAPTURE_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != CAPTURE_MAGIC {
+            return Err(invalid_data("Not a capture or unsupported capture version"));
+        }
+
+        let side = match read_array::<SIDE_SIZE>(&mut reader)? {
+            [0] => CaptureSide::Client,
+            [1] => CaptureSide::Server,
+            _ => return Err(invalid_data("Invalid capture side")),
+        };
+
+        Ok(Self {
+            reader,
+            side,
+        })
+    }
+
+    pub fn side(&self) -> CaptureSide {
+        self.side
+    }
+
+    /// next record, None at the end of the capture
+    pub fn read_record(&mut self) -> io::Result<Option<CaptureRecord>> {
+        let mut timestamp = [0; TIMESTAMP_SIZE];
+        match self.reader.read_exact(&mut timestamp) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let direction = match read_array::<DIRECTION_SIZE>(&mut self.reader)? {
+            [0] => Direction::Incoming,
+            [1] => Direction::Outgoing,
+            _ => return Err(invalid_data("Invalid frame direction")),
+        };
+
+        let length = u32::from_le_bytes(read_array::<LENGTH_SIZE>(&mut self.reader)?);
+        let mut frame = vec![0; length as usize];
+        self.reader.read_exact(&mut frame)?;
+
+        Ok(Some(CaptureRecord {
+            timestamp: Duration::from_micros(u64::from_le_bytes(timestamp)),
+            direction,
+            frame: frame.into(),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut array = [0; N];
+    reader.read_exact(&mut array)?;
+    Ok(array)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
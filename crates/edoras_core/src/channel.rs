@@ -0,0 +1,165 @@
+use crate::chunk::CHUNK_SIZE;
+use crate::codec::Frame;
+use crate::message::{Message, MessageType};
+use std::collections::{HashMap, VecDeque};
+
+pub type ChannelId = u16;
+
+/// protocol messages like handshake, auth and errors, frames without a channel id belong to it
+pub const CONTROL_CHANNEL: ChannelId = 0;
+pub const CHAT_CHANNEL: ChannelId = 1;
+pub const BULK_CHANNEL: ChannelId = 2;
+
+/// bytes a channel may send per round before the next channel gets its turn
+const QUANTUM: usize = CHUNK_SIZE;
+
+/// orders outgoing frames so that no channel can starve the others
+///
+/// channels take turns with a deficit round robin over the frame sizes, so a bulk transfer only
+/// gets its fair share of the connection. Ping, Pong and Disconnect skip all queues, a Ping or
+/// Pong replaces the one of its type that is still queued, so they can not pile up
+#[derive(Debug, Default)]
+pub struct FrameScheduler {
+    urgent: VecDeque<(MessageType, Frame)>,
+    channels: HashMap<ChannelId, ChannelQueue>,
+    active: VecDeque<ChannelId>, // channels with queued frames in the order of their turns
+    next_seq: u64,
+}
+
+#[derive(Debug, Default)]
+struct ChannelQueue {
+    frames: VecDeque<QueuedFrame>,
+    deficit: usize,
+}
+
+#[derive(Debug)]
+struct QueuedFrame {
+    seq: u64, // order of the push over all channels
+    droppable: bool,
+    frame: Frame,
+}
+
+// IMPLEMENTATION
+
+impl FrameScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// queues the frame of the message on the channel of the message
+    pub fn push(&mut self, message: &Message, frame: Frame) {
+        let mtype = message.mtype();
+        if is_urgent(mtype) {
+            // only the newest keepalive matters, a Disconnect is never replaced
+            if mtype != MessageType::Disconnect {
+                self.urgent.retain(|(queued, _)| *queued != mtype);
+            }
+            self.urgent.push_back((mtype, frame));
+            return;
+        }
+
+        let channel = message.channel();
+        let queue = self.channels.entry(channel).or_default();
+        if queue.frames.is_empty() {
+            self.active.push_back(channel);
+        }
+        queue.frames.push_back(QueuedFrame {
+            seq: self.next_seq,
+            droppable: is_droppable(message),
+            frame,
+        });
+        self.next_seq += 1;
+    }
+
+    /// next frame to send
+    pub fn pop(&mut self) -> Option<Frame> {
+        if let Some((_, frame)) = self.urgent.pop_front() {
+            return Some(frame);
+        }
+
+        // every round adds a quantum to the deficit, so a frame is found eventually
+        while let Some(&channel) = self.active.front() {
+            let queue = self.channels.get_mut(&channel)?;
+            let size = queue.frames.front().map_or(0, |queued| queued.frame.len());
+
+            if queue.deficit < size {
+                queue.deficit += QUANTUM;
+                self.active.rotate_left(1);
+                continue;
+            }
+
+            queue.deficit -= size;
+            let frame = queue.frames.pop_front().map(|queued| queued.frame);
+            if queue.frames.is_empty() {
+                // an idle channel must not save up its deficit
+                queue.deficit = 0;
+                self.active.pop_front();
+            }
+
+            return frame;
+        }
+
+        None
+    }
+
+    /// removes the droppable frame that was pushed first of all channels, `None` if no frame
+    /// may be dropped. Urgent frames, replies and the frames of streamed fields are never dropped
+    pub fn drop_oldest(&mut self) -> Option<Frame> {
+        let (&channel, index, _) = self
+            .channels
+            .iter()
+            .filter_map(|(channel, queue)| {
+                let (index, queued) = queue
+                    .frames
+                    .iter()
+                    .enumerate()
+                    .find(|(_, queued)| queued.droppable)?;
+                Some((channel, index, queued.seq))
+            })
+            .min_by_key(|&(_, _, seq)| seq)?;
+
+        let queue = self.channels.get_mut(&channel)?;
+        let frame = queue.frames.remove(index).map(|queued| queued.frame);
+        if queue.frames.is_empty() {
+            queue.deficit = 0;
+            self.active.retain(|&active| active != channel);
+        }
+
+        frame
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.urgent.is_empty() && self.active.is_empty()
+    }
+
+    /// number of queued frames
+    pub fn len(&self) -> usize {
+        self.urgent.len()
+            + self
+                .channels
+                .values()
+                .map(|queue| queue.frames.len())
+                .sum::<usize>()
+    }
+}
+
+/// messages that keep the connection alive or end it are never queued behind other frames
+pub(crate) fn is_urgent(mtype: MessageType) -> bool {
+    matches!(
+        mtype,
+        MessageType::Ping | MessageType::Pong | MessageType::Disconnect
+    )
+}
+
+/// a reply is awaited by a request and a chunk or a message with streamed fields is part of a
+/// stream, dropping them would break the peer. Everything else may be dropped to make room
+fn is_droppable(message: &Message) -> bool {
+    !matches!(
+        message.mtype(),
+        MessageType::Okay | MessageType::Error | MessageType::Chunk
+    ) && message.request_id().is_none()
+        && message
+            .fields()
+            .iter()
+            .all(|field| field.stream_id().is_none())
+}
@@ -0,0 +1,337 @@
+use crate::errors::MessageError;
+use crate::message::{Message, MessageType};
+use crate::payload::{read_field, Payload, PayloadField};
+use bytes::Bytes;
+use futures::channel::mpsc::{self, Receiver, Sender};
+use futures::{ready, SinkExt, Stream};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+pub type StreamId = u32;
+
+/// maximum size of the data of a single [`Chunk`]
+pub const CHUNK_SIZE: usize = 32 * 1024;
+
+/// chunks a [`ChunkStream`] buffers before the [`StreamRouter`] waits for its consumer
+pub const STREAM_BUFFER: usize = 4;
+
+/// continuation frame that carries a part of a streamed field
+///
+/// the chunks of a stream are sent in order after the message declaring the streamed field, the
+/// last chunk has `last` set and may be empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub stream_id: StreamId,
+    pub last: bool,
+    pub data: Bytes,
+}
+
+/// chunks of a streamed field as they are received
+///
+/// ends after the last chunk, fails with [`MessageError::ConnectionClosed`] if the connection
+/// closed and its [`StreamRouter`] was dropped before the last chunk arrived
+#[derive(Debug)]
+pub struct ChunkStream {
+    stream_id: StreamId,
+    receiver: Receiver<Bytes>,
+    complete: Arc<AtomicBool>, // set by the router once the last chunk was routed
+    ended: bool,
+}
+
+/// hands the received chunks to the streams of their fields
+///
+/// only the protocol side of streamed fields lives here, neither the server nor the client use
+/// it yet. No message type of the core protocol declares streamed fields, so the server ignores
+/// Chunk frames until a message type with attachments is added
+///
+/// every stream buffers at most [`STREAM_BUFFER`] chunks, a slow consumer makes the router wait
+/// and so stops the connection from being read instead of buffering the whole field
+#[derive(Debug, Default)]
+pub struct StreamRouter {
+    streams: HashMap<StreamId, StreamSender>,
+}
+
+#[derive(Debug)]
+struct StreamSender {
+    sender: Sender<Bytes>,
+    complete: Arc<AtomicBool>,
+}
+
+// IMPLEMENTATION
+
+impl Chunk {
+    pub fn new(stream_id: StreamId, data: impl Into<Bytes>) -> Self {
+        Self {
+            stream_id,
+            last: false,
+            data: data.into(),
+        }
+    }
+
+    /// marks the end of the stream
+    pub fn last(stream_id: StreamId) -> Self {
+        Self {
+            stream_id,
+            last: true,
+            data: Bytes::new(),
+        }
+    }
+
+    /// splits the data into chunks of at most [`CHUNK_SIZE`] bytes without copying it, the final
+    /// marker is not included
+    pub fn split(stream_id: StreamId, data: Bytes) -> impl Iterator<Item = Chunk> {
+        let count = data.len().div_ceil(CHUNK_SIZE);
+        (0..count).map(move |i| {
+            let end = data.len().min((i + 1) * CHUNK_SIZE);
+            Chunk::new(stream_id, data.slice(i * CHUNK_SIZE..end))
+        })
+    }
+}
+
+impl Payload for Chunk {
+    const MESSAGE_TYPE: MessageType = MessageType::Chunk;
+
+    fn to_fields(&self) -> Vec<Bytes> {
+        vec![
+            self.stream_id.to_field(),
+            self.last.to_field(),
+            self.data.clone(),
+        ]
+    }
+
+    fn from_fields(fields: &[&[u8]]) -> Result<Self, MessageError> {
+        let [stream_id, last, data] = fields else {
+            return Err(MessageError::MalformedMessage(Self::MESSAGE_TYPE));
+        };
+
+        Ok(Self {
+            stream_id: read_field(Self::MESSAGE_TYPE, stream_id)?,
+            last: read_field(Self::MESSAGE_TYPE, last)?,
+            data: Bytes::copy_from_slice(data),
+        })
+    }
+
+    /// shares the data with the message instead of copying it
+    fn from_message(message: &Message) -> Result<Self, MessageError> {
+        if message.mtype() != Self::MESSAGE_TYPE {
+            return Err(MessageError::UnexpectedMessage(message.mtype()));
+        }
+
+        let chunk = Self::from_fields(&message.data_ref())?;
+        Ok(Self {
+            data: message.data().swap_remove(2),
+            ..chunk
+        })
+    }
+}
+
+impl ChunkStream {
+    pub fn stream_id(&self) -> StreamId {
+        self.stream_id
+    }
+}
+
+impl Stream for ChunkStream {
+    type Item = Result<Bytes, MessageError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.ended {
+            return Poll::Ready(None);
+        }
+
+        if let Some(data) = ready!(Pin::new(&mut self.receiver).poll_next(cx)) {
+            return Poll::Ready(Some(Ok(data)));
+        }
+
+        // the channel also ends when the router is dropped, the flag tells a complete stream
+        // apart from a truncated one no matter how full the channel was
+        self.ended = true;
+        match self.complete.load(Ordering::Acquire) {
+            true => Poll::Ready(None),
+            false => Poll::Ready(Some(Err(MessageError::ConnectionClosed))),
+        }
+    }
+}
+
+impl StreamRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// opens a stream for every streamed field of the message, in the order of the fields
+    pub fn accept(&mut self, message: &Message) -> Vec<ChunkStream> {
+        message
+            .stream_ids()
+            .into_iter()
+            .map(|stream_id| self.open(stream_id))
+            .collect()
+    }
+
+    /// opens the stream, chunks of a stream that is not open are dropped
+    pub fn open(&mut self, stream_id: StreamId) -> ChunkStream {
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let complete = Arc::new(AtomicBool::new(false));
+        self.streams.insert(
+            stream_id,
+            StreamSender {
+                sender,
+                complete: complete.clone(),
+            },
+        );
+
+        ChunkStream {
+            stream_id,
+            receiver,
+            complete,
+            ended: false,
+        }
+    }
+
+    /// passes the chunk on to its stream and waits while the stream is full, returns false if
+    /// the stream is not open
+    pub async fn route(&mut self, chunk: Chunk) -> bool {
+        let Some(stream) = self.streams.get_mut(&chunk.stream_id) else {
+            return false;
+        };
+
+        // the receiving side may have been dropped already, the rest of the stream is discarded
+        if !chunk.data.is_empty() {
+            let _ = stream.sender.send(chunk.data).await;
+        }
+
+        if chunk.last {
+            stream.complete.store(true, Ordering::Release);
+            self.streams.remove(&chunk.stream_id);
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{MessageDecoder, MessageEncoder};
+    use crate::message::MessageBuilder;
+    use futures::{FutureExt, StreamExt};
+    use std::pin::pin;
+
+    #[test]
+    fn chunk_message_shares_the_data() {
+        let data = Bytes::from(vec![1; CHUNK_SIZE]);
+        let message = Chunk::new(3, data.clone()).to_message();
+
+        assert_eq!(message.data()[2].as_ptr(), data.as_ptr());
+    }
+
+    #[async_std::test]
+    async fn streamed_field_arrives_through_router() {
+        let data = Bytes::from((0..3 * CHUNK_SIZE + 7).map(|i| i as u8).collect::<Vec<_>>());
+        let message = MessageBuilder::new()
+            .with_type(MessageType::extension(0x80).unwrap())
+            .with_field(b"attachment".to_vec())
+            .with_streamed_field(9)
+            .build();
+
+        let encoder = MessageEncoder::new();
+        let mut wire = Vec::new();
+        encoder.send(&message, &mut wire).await.unwrap();
+        let chunks = futures::stream::iter([data.clone()]);
+        encoder.send_stream(9, chunks, &mut wire).await.unwrap();
+
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&wire);
+        let received = decoder.decode().unwrap().unwrap();
+
+        let mut router = StreamRouter::new();
+        let [stream] = <[ChunkStream; 1]>::try_from(router.accept(&received)).unwrap();
+        assert_eq!(stream.stream_id(), 9);
+
+        let consumer = async_std::task::spawn(async move {
+            let parts: Vec<Bytes> = stream.map(Result::unwrap).collect().await;
+            parts.concat()
+        });
+        while let Some(message) = decoder.decode().unwrap() {
+            assert!(router.route(Chunk::from_message(&message).unwrap()).await);
+        }
+
+        assert_eq!(consumer.await, data);
+    }
+
+    #[async_std::test]
+    async fn router_waits_for_slow_consumer() {
+        let mut router = StreamRouter::new();
+        let mut stream = router.open(1);
+
+        for _ in 0..STREAM_BUFFER {
+            assert!(router.route(Chunk::new(1, vec![0; 4])).await);
+        }
+        assert!(router
+            .route(Chunk::new(1, vec![1; 4]))
+            .now_or_never()
+            .is_none());
+
+        // the chunk of the route that had to wait is queued already
+        for _ in 0..2 {
+            assert!(stream.next().await.unwrap().is_ok());
+        }
+        assert!(router.route(Chunk::new(1, vec![2; 4])).await);
+    }
+
+    #[async_std::test]
+    async fn dropped_router_fails_open_streams() {
+        let mut router = StreamRouter::new();
+        let mut stream = router.open(1);
+        for _ in 0..STREAM_BUFFER {
+            router.route(Chunk::new(1, vec![0; 4])).await;
+        }
+        drop(router);
+
+        let last = stream.by_ref().collect::<Vec<_>>().await.pop().unwrap();
+        assert!(matches!(last, Err(MessageError::ConnectionClosed)));
+    }
+
+    #[async_std::test]
+    async fn router_dropped_while_waiting_fails_full_stream() {
+        let mut router = StreamRouter::new();
+        let mut stream = router.open(1);
+        for _ in 0..STREAM_BUFFER {
+            router.route(Chunk::new(1, vec![0; 4])).await;
+        }
+        {
+            let mut waiting = pin!(router.route(Chunk::new(1, vec![1; 4])));
+            assert!(futures::poll!(&mut waiting).is_pending());
+        }
+        drop(router);
+
+        let mut items = stream.by_ref().collect::<Vec<_>>().await;
+        assert!(matches!(
+            items.pop(),
+            Some(Err(MessageError::ConnectionClosed))
+        ));
+        assert!(items.iter().all(Result::is_ok));
+    }
+
+    #[async_std::test]
+    async fn complete_stream_ends_without_error() {
+        let mut router = StreamRouter::new();
+        let stream = router.open(1);
+        router.route(Chunk::new(1, vec![0; 4])).await;
+        router.route(Chunk::last(1)).await;
+        drop(router);
+
+        let items = stream.collect::<Vec<_>>().await;
+        assert_eq!(items.len(), 1);
+        assert!(items[0].is_ok());
+    }
+
+    #[test]
+    fn chunks_of_unknown_streams_are_rejected() {
+        let mut router = StreamRouter::new();
+        let routed = router.route(Chunk::last(3)).now_or_never();
+        assert_eq!(routed, Some(false));
+    }
+}
@@ -0,0 +1,932 @@
+use crate::channel::{ChannelId, CONTROL_CHANNEL};
+use crate::chunk::{Chunk, StreamId};
+use crate::compression::{Compression, COMPRESSION_THRESHOLD};
+use crate::encoding::{Encoding, MAX_VARINT_SIZE};
+use crate::errors::MessageError;
+use crate::limits::DecodeLimits;
+use crate::message::{
+    BaseLength, FrameFlags, Message, MessageBuilder, MessageField, MessageType, MessageTypeCode,
+    RequestId, BASE_LENGTH_SIZE, CHANNEL_ID_SIZE, FRAME_FLAGS_SIZE, HEADER, HEADER_SIZE,
+    MESSAGE_TYPE_SIZE, REQUEST_ID_SIZE, STREAMED_FIELD, STREAM_ID_SIZE,
+};
+use crate::payload::Payload;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
+use std::collections::HashMap;
+use std::io::{self, IoSlice};
+use std::ops::Range;
+
+const READ_BUFFER_SIZE: usize = 4096;
+const INLINE_FIELD_SIZE: usize = 256; // smaller fields are copied into the frame instead of shared
+pub(crate) const CHECKSUM_SIZE: usize = size_of::<u32>();
+
+const FLAG_REQUEST_ID: FrameFlags = 0b0000_0001;
+const FLAG_CHECKSUM: FrameFlags = 0b0000_0010;
+const FLAG_DEFLATE: FrameFlags = 0b0000_0100;
+const FLAG_ZSTD: FrameFlags = 0b0000_1000;
+const FLAG_COMPRESSION: FrameFlags = FLAG_DEFLATE | FLAG_ZSTD;
+const FLAG_CHANNEL: FrameFlags = 0b0001_0000;
+const FLAG_COMPACT: FrameFlags = 0b0010_0000; // counts and lengths are varints, see Encoding::V2
+const KNOWN_FLAGS: FrameFlags =
+    FLAG_REQUEST_ID | FLAG_CHECKSUM | FLAG_COMPRESSION | FLAG_CHANNEL | FLAG_COMPACT;
+
+/// incrementally decodes messages from arbitrary chunks of bytes
+///
+/// bytes preceding a valid header are discarded, so the decoder resynchronizes on its own
+/// after garbage was received. frames of both encodings are understood, their flags tell them
+/// apart
+///
+/// the checksum trails the frame, so a corrupted length is only noticed once the frame it claims
+/// is complete or breaks a limit. the frames buffered behind it are decoded after that
+#[derive(Debug, Default)]
+pub struct MessageDecoder {
+    buf: BytesMut,
+    needed: usize, // minimum buffer length before the next frame can be complete
+    last_frame: Option<Bytes>,
+
+    config: DecoderConfig,
+}
+
+/// encodes messages into frames
+///
+/// encoders with the same settings produce the same frames, so a frame can be reused for every
+/// peer whose encoder compares equal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageEncoder {
+    checksum: bool,
+    channels: bool,
+    encoding: Encoding,
+    compression: Compression,
+    compression_threshold: usize,
+}
+
+/// settings that decide which frames are accepted
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecoderConfig {
+    limits: DecodeLimits,
+    type_limits: HashMap<MessageType, DecodeLimits>,
+    require_checksum: bool,
+    compression: Compression,
+}
+
+/// an encoded frame, the fields of the message are shared and not copied
+///
+/// cloning is cheap, so one frame can be written to many streams without encoding it again
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    chunks: Vec<Bytes>,
+}
+
+pub(crate) enum Decoded {
+    Frame(Result<Message, MessageError>, Bytes), // complete frame, already removed from the buffer
+    Incomplete(usize),                           // minimum number of bytes needed to continue
+}
+
+enum Parsed {
+    Frame(FrameLayout),
+    Incomplete(usize),
+}
+
+/// where the parts of a complete frame are found in the buffer
+struct FrameLayout {
+    mtype: Result<MessageType, MessageError>,
+    request_id: Option<RequestId>,
+    channel: ChannelId,
+    size: usize,
+    body: Option<Bytes>, // decompressed body, otherwise the fields point into the frame itself
+    fields: Vec<FieldLayout>,
+}
+
+struct FieldLayout {
+    range: Range<usize>,
+    streamed: bool,
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    needed: usize,
+}
+
+// IMPLEMENTATION
+
+impl MessageDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// sets the limits for all message types without specific limits
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    /// sets the limits for a single message type
+    pub fn with_type_limits(mut self, mtype: MessageType, limits: DecodeLimits) -> Self {
+        self.config.type_limits.insert(mtype, limits);
+        self
+    }
+
+    pub fn limits(&self, mtype: MessageType) -> DecodeLimits {
+        self.config.limits(Some(mtype))
+    }
+
+    /// rejects frames without a checksum, used once checksums were negotiated
+    pub fn set_require_checksum(&mut self, require_checksum: bool) {
+        self.config.require_checksum = require_checksum;
+    }
+
+    pub fn require_checksum(&self) -> bool {
+        self.config.require_checksum
+    }
+
+    /// accepts frames compressed with the algorithm, used once compression was negotiated
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.config.compression = compression;
+    }
+
+    /// takes the bytes of the last decoded frame as they were received, e.g. to capture them
+    pub fn take_last_frame(&mut self) -> Option<Bytes> {
+        self.last_frame.take()
+    }
+
+    /// number of bytes that are buffered but not yet decoded
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// returns the next complete message or `None` if more data has to be fed
+    pub fn decode(&mut self) -> Result<Option<Message>, MessageError> {
+        if self.buf.len() < self.needed || !self.sync() {
+            return Ok(None);
+        }
+
+        let decoded = match decode_frame(&mut self.buf, &self.config) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                // the lengths of a broken frame can not be trusted, so only its header is skipped
+                // to resync on the next one
+                self.buf.advance(HEADER_SIZE);
+                self.needed = 0;
+                self.last_frame = None;
+                return Err(e);
+            }
+        };
+
+        match decoded {
+            Decoded::Frame(message, frame) => {
+                self.needed = 0;
+                self.last_frame = Some(frame);
+                message.map(Some)
+            }
+            Decoded::Incomplete(needed) => {
+                self.needed = needed;
+                Ok(None)
+            }
+        }
+    }
+
+    /// reads from the stream until a complete message can be decoded
+    ///
+    /// data that was read is always fed to the decoder, so dropping the future does not lose any
+    /// bytes
+    pub async fn recv<S>(&mut self, stream: &mut S) -> Result<Message, MessageError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+
+        loop {
+            if let Some(message) = self.decode()? {
+                return Ok(message);
+            }
+
+            let read = match stream.read(&mut buf).await {
+                Ok(0) => return Err(MessageError::ConnectionClosed),
+                Ok(read) => read,
+                Err(e) => return Err(MessageError::ReadError(e)),
+            };
+
+            self.feed(&buf[..read]);
+        }
+    }
+
+    /// discards everything before the next header, returns false if no header is buffered
+    fn sync(&mut self) -> bool {
+        match self
+            .buf
+            .windows(HEADER_SIZE)
+            .position(|window| window == HEADER)
+        {
+            Some(0) => true,
+            Some(pos) => {
+                tracing::debug!("Skipping garbage before header | {:x?}", &self.buf[..pos]);
+                self.buf.advance(pos);
+                true
+            }
+            None => {
+                // keep a possibly incomplete header at the end of the buffer
+                let keep = self.buf.len().min(HEADER_SIZE - 1);
+                self.buf.advance(self.buf.len() - keep);
+                false
+            }
+        }
+    }
+}
+
+impl MessageEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// compresses bodies that reach the threshold, should only be enabled once negotiated
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// appends a CRC32 checksum to every frame, should only be enabled once negotiated
+    pub fn with_checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn set_checksum(&mut self, checksum: bool) {
+        self.checksum = checksum;
+    }
+
+    /// sends the channel ids of the messages, should only be enabled once negotiated. otherwise
+    /// every message is sent on the control channel
+    pub fn with_channels(mut self, channels: bool) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn set_channels(&mut self, channels: bool) {
+        self.channels = channels;
+    }
+
+    /// writes counts and lengths with the encoding, [`Encoding::V2`] should only be used once
+    /// the protocol version was negotiated
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// encodes the message into a frame that shares the bigger fields of the message
+    pub fn encode(&self, message: &Message) -> Frame {
+        let mut flags: FrameFlags = 0;
+        if message.request_id().is_some() {
+            flags |= FLAG_REQUEST_ID;
+        }
+        if self.checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        let channel = message.channel();
+        if self.channels && channel != CONTROL_CHANNEL {
+            flags |= FLAG_CHANNEL;
+        }
+        if self.encoding == Encoding::V2 {
+            flags |= FLAG_COMPACT;
+        }
+
+        let compressed = self.compress(message);
+        if compressed.is_some() {
+            flags |= compression_flag(self.compression);
+        }
+
+        let mut chunks = vec![];
+        let mut buf = BytesMut::new();
+
+        buf.put_slice(&HEADER);
+        buf.put_slice(&message.mtype().to_code().to_le_bytes());
+        buf.put_slice(&flags.to_le_bytes());
+
+        if let Some(request_id) = message.request_id() {
+            buf.put_slice(&request_id.to_le_bytes());
+        }
+
+        if flags & FLAG_CHANNEL != 0 {
+            buf.put_slice(&channel.to_le_bytes());
+        }
+
+        match compressed {
+            Some(body) => {
+                self.encoding.put_length(&mut buf, body.len() as BaseLength);
+                chunks.push(buf.split().freeze());
+                chunks.push(body);
+            }
+            None => {
+                self.encoding.put_length(&mut buf, message.field_count());
+                for field in message.fields() {
+                    self.encoding.put_length(&mut buf, field.wire_length());
+
+                    if field.data.len() < INLINE_FIELD_SIZE {
+                        buf.put_slice(&field.data);
+                    } else {
+                        chunks.push(buf.split().freeze());
+                        chunks.push(field.data.clone());
+                    }
+                }
+            }
+        }
+
+        if self.checksum {
+            let mut hasher = crc32fast::Hasher::new();
+            chunks.iter().for_each(|chunk| hasher.update(chunk));
+            hasher.update(&buf);
+            buf.put_slice(&hasher.finalize().to_le_bytes());
+        }
+
+        if !buf.is_empty() {
+            chunks.push(buf.freeze());
+        }
+
+        Frame {
+            chunks,
+        }
+    }
+
+    /// appends the encoded frame of the message to the buffer
+    pub fn encode_into(&self, message: &Message, buf: &mut BytesMut) {
+        let frame = self.encode(message);
+
+        buf.reserve(frame.len());
+        for chunk in &frame.chunks {
+            buf.put_slice(chunk);
+        }
+    }
+
+    /// compressed body of the message, `None` if it is too small or does not shrink
+    fn compress(&self, message: &Message) -> Option<Bytes> {
+        if self.compression == Compression::None {
+            return None;
+        }
+
+        let length_size = self.encoding.max_length_size();
+        let size = length_size
+            + message
+                .fields()
+                .iter()
+                .map(|field| length_size + field.data.len())
+                .sum::<usize>();
+        if size < self.compression_threshold {
+            return None;
+        }
+
+        let mut body = Vec::with_capacity(size);
+        self.encoding.put_length(&mut body, message.field_count());
+        for field in message.fields() {
+            self.encoding.put_length(&mut body, field.wire_length());
+            body.extend_from_slice(&field.data);
+        }
+
+        let compressed = self.compression.compress(&body);
+        (compressed.len() < size).then(|| Bytes::from(compressed))
+    }
+
+    /// encodes the message and writes the frame to the stream
+    pub async fn send<S>(&self, message: &Message, stream: &mut S) -> Result<(), MessageError>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        self.encode(message).send(stream).await
+    }
+
+    /// sends the data of a streamed field as [`Chunk`]s followed by the final marker
+    ///
+    /// has to be called after the message declaring the field was sent. the chunks are written
+    /// one after another, a connection that carries other channels at the same time should push
+    /// the chunk frames through a [`FrameScheduler`](crate::FrameScheduler) instead
+    pub async fn send_stream<S, D>(
+        &self,
+        stream_id: StreamId,
+        mut data: D,
+        stream: &mut S,
+    ) -> Result<(), MessageError>
+    where
+        S: AsyncWrite + Unpin,
+        D: futures::Stream<Item = Bytes> + Unpin,
+    {
+        while let Some(data) = data.next().await {
+            for chunk in Chunk::split(stream_id, data) {
+                self.send(&chunk.to_message(), stream).await?;
+            }
+        }
+
+        self.send(&Chunk::last(stream_id).to_message(), stream)
+            .await
+    }
+}
+
+impl Default for MessageEncoder {
+    fn default() -> Self {
+        Self {
+            checksum: false,
+            channels: false,
+            encoding: Encoding::V1,
+            compression: Compression::None,
+            compression_threshold: COMPRESSION_THRESHOLD,
+        }
+    }
+}
+
+impl Frame {
+    /// size of the frame in bytes
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(Bytes::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// the frame as one contiguous buffer, only copies if it consists of several chunks
+    pub fn to_bytes(&self) -> Bytes {
+        match self.chunks.as_slice() {
+            [] => Bytes::new(),
+            [chunk] => chunk.clone(),
+            chunks => Bytes::from(chunks.concat()),
+        }
+    }
+
+    /// writes all chunks of the frame to the stream with vectored writes
+    pub async fn send<S>(&self, stream: &mut S) -> Result<(), MessageError>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        tracing::debug!("Sending message | {:?}", self.chunks);
+
+        let mut slices: Vec<IoSlice> = self.chunks.iter().map(|c| IoSlice::new(c)).collect();
+        let mut slices = slices.as_mut_slice();
+
+        while !slices.is_empty() {
+            match stream.write_vectored(slices).await {
+                Ok(0) => {
+                    return Err(MessageError::WriteError(io::ErrorKind::WriteZero.into()));
+                }
+                Ok(written) => IoSlice::advance_slices(&mut slices, written),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(MessageError::WriteError(e)),
+            }
+        }
+
+        // buffered streams like TLS may hold back the end of the frame otherwise
+        stream.flush().await.map_err(MessageError::WriteError)
+    }
+}
+
+impl DecoderConfig {
+    pub(crate) fn with_limits(limits: DecodeLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// limits of the message type, unknown types get the default limits
+    fn limits(&self, mtype: Option<MessageType>) -> DecodeLimits {
+        mtype
+            .and_then(|mtype| self.type_limits.get(&mtype).copied())
+            .unwrap_or(self.limits)
+    }
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            needed: 0,
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.buf.len() - self.pos < n {
+            self.needed = self.pos + n;
+            return None;
+        }
+
+        let data = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Some(data)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N).map(|data| data.try_into().unwrap())
+    }
+
+    /// a count or length in the encoding, `None` if it is not buffered completely
+    fn take_length(&mut self, encoding: Encoding) -> Result<Option<BaseLength>, MessageError> {
+        match encoding {
+            Encoding::V1 => Ok(self
+                .take_array::<BASE_LENGTH_SIZE>()
+                .map(BaseLength::from_le_bytes)),
+            Encoding::V2 => self.take_varint(),
+        }
+    }
+
+    fn take_varint(&mut self) -> Result<Option<BaseLength>, MessageError> {
+        let mut value: BaseLength = 0;
+
+        for i in 0..MAX_VARINT_SIZE {
+            let Some(&[byte]) = self.take(1) else {
+                return Ok(None);
+            };
+
+            let bits = (byte & 0x7f) as BaseLength;
+            // the last byte only has room for the top 4 bits of a u32
+            if i == MAX_VARINT_SIZE - 1 && bits > 0x0f {
+                return Err(MessageError::InvalidVarint);
+            }
+            value |= bits << (7 * i);
+
+            if byte & 0x80 == 0 {
+                return Ok(Some(value));
+            }
+        }
+
+        Err(MessageError::InvalidVarint)
+    }
+}
+
+/// decodes a single frame from the start of the buffer, the limits are chosen by message type
+///
+/// a complete frame is split off the buffer and its fields share the memory of it. a frame with
+/// an unknown message type is still consumed as a whole, so the stream stays in sync and the
+/// error is returned inside of [`Decoded::Frame`]
+pub(crate) fn decode_frame(
+    buf: &mut BytesMut,
+    config: &DecoderConfig,
+) -> Result<Decoded, MessageError> {
+    let layout = match parse_frame(buf, config)? {
+        Parsed::Frame(layout) => layout,
+        Parsed::Incomplete(needed) => return Ok(Decoded::Incomplete(needed)),
+    };
+
+    let frame = buf.split_to(layout.size).freeze();
+    let source = layout.body.unwrap_or_else(|| frame.clone());
+    let fields = layout
+        .fields
+        .into_iter()
+        .map(|field| MessageField {
+            length: field.range.len() as BaseLength,
+            data: source.slice(field.range),
+            streamed: field.streamed,
+        })
+        .collect();
+
+    let message = layout.mtype.map(|mtype| {
+        let mut builder = MessageBuilder::new()
+            .with_type(mtype)
+            .with_message_fields(fields);
+        if let Some(request_id) = layout.request_id {
+            builder = builder.with_request_id(request_id);
+        }
+        builder = builder.with_channel(layout.channel);
+        builder.build()
+    });
+    tracing::debug!("Decoded message | {:?}", message);
+
+    Ok(Decoded::Frame(message, frame))
+}
+
+/// checks the frame at the start of the buffer without copying any of it
+fn parse_frame(buf: &[u8], config: &DecoderConfig) -> Result<Parsed, MessageError> {
+    let mut cursor = Cursor::new(buf);
+
+    let Some(header) = cursor.take(HEADER_SIZE) else {
+        return Ok(Parsed::Incomplete(cursor.needed));
+    };
+
+    if header != HEADER {
+        return Err(MessageError::InvalidMessage(header.to_vec()));
+    }
+
+    let Some(code) = cursor.take_array::<MESSAGE_TYPE_SIZE>() else {
+        return Ok(Parsed::Incomplete(cursor.needed));
+    };
+    let mtype = MessageType::from_code(MessageTypeCode::from_le_bytes(code));
+    let limits = config.limits(mtype.as_ref().ok().copied());
+
+    let Some(flags) = cursor.take_array::<FRAME_FLAGS_SIZE>() else {
+        return Ok(Parsed::Incomplete(cursor.needed));
+    };
+    let flags = FrameFlags::from_le_bytes(flags);
+
+    // unknown flags may change the layout of the frame, so it can not be skipped
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(MessageError::InvalidFlags(flags));
+    }
+
+    if config.require_checksum && flags & FLAG_CHECKSUM == 0 {
+        return Err(MessageError::MissingChecksum);
+    }
+
+    let encoding = match flags & FLAG_COMPACT {
+        0 => Encoding::V1,
+        _ => Encoding::V2,
+    };
+
+    let mut request_id = None;
+    if flags & FLAG_REQUEST_ID != 0 {
+        let Some(id) = cursor.take_array::<REQUEST_ID_SIZE>() else {
+            return Ok(Parsed::Incomplete(cursor.needed));
+        };
+        request_id = Some(RequestId::from_le_bytes(id));
+    }
+
+    let mut channel = CONTROL_CHANNEL;
+    if flags & FLAG_CHANNEL != 0 {
+        let Some(id) = cursor.take_array::<CHANNEL_ID_SIZE>() else {
+            return Ok(Parsed::Incomplete(cursor.needed));
+        };
+        channel = ChannelId::from_le_bytes(id);
+    }
+
+    let (body, fields) = if flags & FLAG_COMPRESSION != 0 {
+        let compression = match flags & FLAG_COMPRESSION {
+            FLAG_DEFLATE => Compression::Deflate,
+            FLAG_ZSTD => Compression::Zstd,
+            _ => return Err(MessageError::InvalidFlags(flags)),
+        };
+        // only the negotiated algorithm is accepted
+        if compression != config.compression {
+            return Err(MessageError::InvalidFlags(flags));
+        }
+
+        let Some(length) = cursor.take_length(encoding)? else {
+            return Ok(Parsed::Incomplete(cursor.needed));
+        };
+        let length = length as usize;
+
+        let frame_size = cursor.pos + length;
+        if frame_size > limits.max_frame_size() {
+            return Err(MessageError::FrameTooLarge(frame_size));
+        }
+
+        let Some(data) = cursor.take(length) else {
+            return Ok(Parsed::Incomplete(cursor.needed));
+        };
+
+        // the checksum covers the compressed bytes, so it is checked before inflating them
+        if !read_checksum(&mut cursor, flags)? {
+            return Ok(Parsed::Incomplete(cursor.needed));
+        }
+
+        let body = Bytes::from(compression.decompress(data, limits.max_frame_size())?);
+        let mut body_cursor = Cursor::new(&body);
+        match decode_body(&mut body_cursor, &limits, encoding)? {
+            Some(fields) if body_cursor.pos == body.len() => (Some(body), fields),
+            _ => return Err(MessageError::InvalidCompression),
+        }
+    } else {
+        let Some(fields) = decode_body(&mut cursor, &limits, encoding)? else {
+            return Ok(Parsed::Incomplete(cursor.needed));
+        };
+        if !read_checksum(&mut cursor, flags)? {
+            return Ok(Parsed::Incomplete(cursor.needed));
+        }
+        (None, fields)
+    };
+
+    Ok(Parsed::Frame(FrameLayout {
+        mtype,
+        request_id,
+        channel,
+        size: cursor.pos,
+        body,
+        fields,
+    }))
+}
+
+/// decodes the field count and the position of each field, `None` if the buffer ends before the
+/// last field
+fn decode_body(
+    cursor: &mut Cursor,
+    limits: &DecodeLimits,
+    encoding: Encoding,
+) -> Result<Option<Vec<FieldLayout>>, MessageError> {
+    let Some(count) = cursor.take_length(encoding)? else {
+        return Ok(None);
+    };
+
+    if count > limits.max_fields() {
+        return Err(MessageError::TooManyFields(count));
+    }
+
+    let mut fields = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let Some(length) = cursor.take_length(encoding)? else {
+            return Ok(None);
+        };
+
+        let streamed = length & STREAMED_FIELD != 0;
+        let length = length & !STREAMED_FIELD;
+        if streamed && length as usize != STREAM_ID_SIZE {
+            return Err(MessageError::InvalidStreamedField(length));
+        }
+
+        if length > limits.max_field_size() {
+            return Err(MessageError::FieldTooLarge(length));
+        }
+
+        let frame_size = cursor.pos + length as usize;
+        if frame_size > limits.max_frame_size() {
+            return Err(MessageError::FrameTooLarge(frame_size));
+        }
+
+        let start = cursor.pos;
+        if cursor.take(length as usize).is_none() {
+            return Ok(None);
+        }
+
+        fields.push(FieldLayout {
+            range: start..cursor.pos,
+            streamed,
+        });
+    }
+
+    Ok(Some(fields))
+}
+
+/// checks the trailing checksum against everything before it, if the frame has one
+///
+/// returns false if the checksum is not buffered yet
+fn read_checksum(cursor: &mut Cursor, flags: FrameFlags) -> Result<bool, MessageError> {
+    if flags & FLAG_CHECKSUM == 0 {
+        return Ok(true);
+    }
+
+    let end = cursor.pos;
+    let Some(checksum) = cursor.take_array::<CHECKSUM_SIZE>() else {
+        return Ok(false);
+    };
+
+    let expected = u32::from_le_bytes(checksum);
+    let actual = crc32fast::hash(&cursor.buf[..end]);
+    if expected != actual {
+        return Err(MessageError::ChecksumMismatch(expected, actual));
+    }
+
+    Ok(true)
+}
+
+fn compression_flag(compression: Compression) -> FrameFlags {
+    match compression {
+        Compression::None => 0,
+        Compression::Deflate => FLAG_DEFLATE,
+        Compression::Zstd => FLAG_ZSTD,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// raw frame of a Ping with the flags, the body is taken as is
+    fn raw_frame(flags: FrameFlags, body: &[u8]) -> Vec<u8> {
+        let mut frame = HEADER.to_vec();
+        frame.push(MessageType::Ping.to_code());
+        frame.push(flags);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    fn message(data: &[u8]) -> Message {
+        MessageBuilder::new()
+            .with_type(MessageType::Ping)
+            .with_field(data.to_vec())
+            .build()
+    }
+
+    #[test]
+    fn huge_field_count_is_rejected() {
+        let mut decoder = MessageDecoder::new();
+        // the fields are not buffered, so a decoder that trusts the count waits for them
+        decoder.feed(&raw_frame(0, &u32::MAX.to_le_bytes()));
+
+        assert!(matches!(
+            decoder.decode(),
+            Err(MessageError::TooManyFields(u32::MAX))
+        ));
+    }
+
+    #[test]
+    fn checksum_mismatch_resyncs_on_next_frame() {
+        let encoder = MessageEncoder::new().with_checksum(true);
+        let mut broken = encoder.encode(&message(b"broken")).to_bytes().to_vec();
+        let field = broken.len() - CHECKSUM_SIZE - 1;
+        broken[field] ^= 0xff;
+
+        let mut decoder = MessageDecoder::new();
+        decoder.set_require_checksum(true);
+        decoder.feed(&broken);
+        decoder.feed(&encoder.encode(&message(b"intact")).to_bytes());
+
+        assert!(matches!(
+            decoder.decode(),
+            Err(MessageError::ChecksumMismatch(_, _))
+        ));
+        let message = decoder.decode().unwrap().unwrap();
+        assert_eq!(message.data(), [Bytes::from_static(b"intact")]);
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn compressed_body_above_frame_limit_is_rejected() {
+        let limits = DecodeLimits::default().with_max_frame_size(16 * 1024);
+        // zeros compress well, so only the inflated body breaks the limit
+        let message = message(&[0; 64 * 1024]);
+
+        for compression in [Compression::Deflate, Compression::Zstd] {
+            let frame = MessageEncoder::new()
+                .with_compression(compression)
+                .encode(&message);
+            assert!(frame.len() < limits.max_frame_size());
+
+            let mut decoder = MessageDecoder::new().with_limits(limits);
+            decoder.set_compression(compression);
+            decoder.feed(&frame.to_bytes());
+
+            assert!(
+                matches!(decoder.decode(), Err(MessageError::FrameTooLarge(_))),
+                "{:?}",
+                compression
+            );
+        }
+    }
+
+    #[test]
+    fn overlong_varints_are_rejected() {
+        // the fifth byte only has room for the top 4 bits of a u32
+        for count in [
+            &[0xff, 0xff, 0xff, 0xff, 0x1f][..],
+            &[0x80, 0x80, 0x80, 0x80, 0x80, 0x00][..],
+        ] {
+            let mut decoder = MessageDecoder::new();
+            decoder.feed(&raw_frame(FLAG_COMPACT, count));
+            assert!(matches!(decoder.decode(), Err(MessageError::InvalidVarint)));
+        }
+
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&raw_frame(FLAG_COMPACT, &[0xff, 0xff, 0xff, 0xff, 0x0f]));
+        assert!(matches!(
+            decoder.decode(),
+            Err(MessageError::TooManyFields(u32::MAX))
+        ));
+    }
+
+    #[test]
+    fn both_encodings_round_trip_across_split_input() {
+        let message = MessageBuilder::new()
+            .with_type(MessageType::Ping)
+            .with_request_id(42)
+            .with_channel(3)
+            .with_field(b"short".to_vec())
+            .with_field(vec![7; 300]) // needs a varint of two bytes
+            .with_field(Vec::new())
+            .build();
+
+        for encoding in [Encoding::V1, Encoding::V2] {
+            let frame = MessageEncoder::new()
+                .with_encoding(encoding)
+                .with_checksum(true)
+                .with_channels(true)
+                .encode(&message)
+                .to_bytes();
+
+            let mut decoder = MessageDecoder::new();
+            let (last, head) = frame.split_last().unwrap();
+            for byte in head {
+                decoder.feed(&[*byte]);
+                assert!(decoder.decode().unwrap().is_none(), "{:?}", encoding);
+            }
+            decoder.feed(&[*last]);
+
+            let decoded = decoder.decode().unwrap().unwrap();
+            assert_eq!(decoded.mtype(), message.mtype());
+            assert_eq!(decoded.request_id(), Some(42));
+            assert_eq!(decoded.channel(), 3);
+            assert_eq!(decoded.data(), message.data());
+            assert_eq!(decoder.take_last_frame(), Some(frame));
+        }
+    }
+}
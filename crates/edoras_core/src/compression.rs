@@ -0,0 +1,67 @@
+use crate::errors::MessageError;
+use crate::handshake::Capabilities;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::io::{Read, Write};
+
+/// frames with a smaller body are sent uncompressed
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// algorithm used to compress the body of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Deflate,
+    Zstd,
+}
+
+// IMPLEMENTATION
+
+impl Compression {
+    /// best algorithm of the negotiated capabilities, zstd is preferred over deflate
+    pub fn from_capabilities(capabilities: Capabilities) -> Self {
+        if capabilities.contains(Capabilities::ZSTD) {
+            Self::Zstd
+        } else if capabilities.contains(Capabilities::DEFLATE) {
+            Self::Deflate
+        } else {
+            Self::None
+        }
+    }
+
+    pub(crate) fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::None => data.to_vec(),
+            Self::Deflate => {
+                let mut encoder = DeflateEncoder::new(vec![], flate2::Compression::default());
+                // writing into a vec can not fail
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Self::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).unwrap(),
+        }
+    }
+
+    /// decompresses at most `max_size` bytes, bigger bodies fail with
+    /// [`MessageError::FrameTooLarge`] before they are fully inflated
+    pub(crate) fn decompress(self, data: &[u8], max_size: usize) -> Result<Vec<u8>, MessageError> {
+        let mut buf = vec![];
+        let limit = max_size as u64 + 1;
+
+        let read = match self {
+            Self::None => return Ok(data.to_vec()),
+            Self::Deflate => DeflateDecoder::new(data).take(limit).read_to_end(&mut buf),
+            Self::Zstd => zstd::stream::read::Decoder::new(data)
+                .and_then(|decoder| decoder.take(limit).read_to_end(&mut buf)),
+        };
+
+        match read {
+            Ok(size) if size > max_size => Err(MessageError::FrameTooLarge(size)),
+            Ok(_) => Ok(buf),
+            Err(_) => Err(MessageError::InvalidCompression),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::payload::PayloadField;
+use bytes::Bytes;
+use edoras_derive::Payload;
+use std::fmt::Display;
+
+pub type DisconnectReasonValue = u16;
+
+const UNKNOWN: DisconnectReasonValue = 0;
+const CLIENT_QUIT: DisconnectReasonValue = 1;
+const SERVER_SHUTDOWN: DisconnectReasonValue = 2;
+const KICKED: DisconnectReasonValue = 3;
+const IDLE_TIMEOUT: DisconnectReasonValue = 4;
+const PROTOCOL_ERROR: DisconnectReasonValue = 5;
+const SLOW_CONSUMER: DisconnectReasonValue = 6;
+
+/// machine-readable reason sent in a Disconnect frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisconnectReason {
+    Unknown,
+    ClientQuit,
+    ServerShutdown,
+    Kicked,
+    IdleTimeout,
+    ProtocolError,
+    /// the peer did not read its frames fast enough
+    SlowConsumer,
+
+    /// a reason this implementation does not know yet
+    Other(DisconnectReasonValue),
+}
+
+/// ends a connection, the peer answers with a Disconnect of its own before the socket is shut
+/// down
+///
+/// the side that receives a Disconnect first echoes it as acknowledgement, the side that sent
+/// it first takes the next Disconnect as acknowledgement
+#[derive(Debug, Clone, PartialEq, Eq, Payload)]
+#[payload(Disconnect)]
+pub struct Disconnect {
+    pub reason: DisconnectReason,
+    pub detail: String,
+}
+
+// IMPLEMENTATION
+
+impl DisconnectReason {
+    pub fn from_code(code: DisconnectReasonValue) -> Self {
+        match code {
+            UNKNOWN => Self::Unknown,
+            CLIENT_QUIT => Self::ClientQuit,
+            SERVER_SHUTDOWN => Self::ServerShutdown,
+            KICKED => Self::Kicked,
+            IDLE_TIMEOUT => Self::IdleTimeout,
+            PROTOCOL_ERROR => Self::ProtocolError,
+            SLOW_CONSUMER => Self::SlowConsumer,
+            code => Self::Other(code),
+        }
+    }
+
+    pub fn to_code(self) -> DisconnectReasonValue {
+        match self {
+            Self::Unknown => UNKNOWN,
+            Self::ClientQuit => CLIENT_QUIT,
+            Self::ServerShutdown => SERVER_SHUTDOWN,
+            Self::Kicked => KICKED,
+            Self::IdleTimeout => IDLE_TIMEOUT,
+            Self::ProtocolError => PROTOCOL_ERROR,
+            Self::SlowConsumer => SLOW_CONSUMER,
+            Self::Other(code) => code,
+        }
+    }
+}
+
+impl Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "Unknown reason"),
+            Self::ClientQuit => write!(f, "Client quit"),
+            Self::ServerShutdown => write!(f, "Server shutdown"),
+            Self::Kicked => write!(f, "Kicked"),
+            Self::IdleTimeout => write!(f, "Idle timeout"),
+            Self::ProtocolError => write!(f, "Protocol error"),
+            Self::SlowConsumer => write!(f, "Slow consumer"),
+            Self::Other(code) => write!(f, "Disconnect reason {}", code),
+        }
+    }
+}
+
+impl PayloadField for DisconnectReason {
+    fn to_field(&self) -> Bytes {
+        self.to_code().to_field()
+    }
+
+    fn from_field(data: &[u8]) -> Option<Self> {
+        DisconnectReasonValue::from_field(data).map(DisconnectReason::from_code)
+    }
+}
+
+impl Disconnect {
+    pub fn new(reason: DisconnectReason, detail: impl Into<String>) -> Self {
+        Self {
+            reason,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl Display for Disconnect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.detail.is_empty() {
+            true => write!(f, "{}", self.reason),
+            false => write!(f, "{}: {}", self.reason, self.detail),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use crate::handshake::ProtocolVersion;
+use crate::message::{BaseLength, BASE_LENGTH_SIZE};
+use bytes::BufMut;
+
+/// protocol version that introduced [`Encoding::V2`]
+pub const COMPACT_PROTOCOL_VERSION: ProtocolVersion = 2;
+
+/// longest LEB128 encoding of a [`BaseLength`]
+pub(crate) const MAX_VARINT_SIZE: usize = 5;
+
+/// how the field count and the lengths of a frame are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Encoding {
+    /// fixed 4-byte little endian integers, understood by every peer
+    #[default]
+    V1,
+    /// LEB128 varints, so small messages only spend a byte per length
+    V2,
+}
+
+// IMPLEMENTATION
+
+impl Encoding {
+    /// encoding of the negotiated protocol version
+    pub fn from_version(version: ProtocolVersion) -> Self {
+        if version >= COMPACT_PROTOCOL_VERSION {
+            Self::V2
+        } else {
+            Self::V1
+        }
+    }
+
+    pub(crate) fn put_length(self, buf: &mut impl BufMut, length: BaseLength) {
+        match self {
+            Self::V1 => buf.put_slice(&length.to_le_bytes()),
+            Self::V2 => put_varint(buf, length),
+        }
+    }
+
+    /// upper bound of the bytes a length takes
+    pub(crate) fn max_length_size(self) -> usize {
+        match self {
+            Self::V1 => BASE_LENGTH_SIZE,
+            Self::V2 => MAX_VARINT_SIZE,
+        }
+    }
+}
+
+/// seven bits per byte starting with the lowest ones, the high bit marks that more bytes follow
+fn put_varint(buf: &mut impl BufMut, mut value: BaseLength) {
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
@@ -0,0 +1,303 @@
+use crate::errors::EndpointError;
+use crate::{HOST, PORT, WS_PORT};
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const EDORAS_SCHEME: &str = "edoras";
+const EDORAS_TLS_SCHEME: &str = "edoras+tls";
+const WS_SCHEME: &str = "ws";
+const WSS_SCHEME: &str = "wss";
+const UNIX_SCHEME: &str = "unix";
+
+/// address a server listens on or a client connects to
+///
+/// | address                  | endpoint           |
+/// |--------------------------|--------------------|
+/// | `edoras://host:port`     | TCP                |
+/// | `edoras+tls://host:port` | TCP with TLS       |
+/// | `ws://host:port`         | WebSocket          |
+/// | `wss://host:port`        | WebSocket with TLS |
+/// | `unix:///path`           | unix socket        |
+///
+/// the port may be left out, [`PORT`] and [`WS_PORT`] are used then. Port 0 lets the server
+/// pick a free port
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    Tcp(NetAddress),
+    WebSocket(NetAddress),
+    Unix(PathBuf),
+}
+
+/// host and port of a TCP or WebSocket endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NetAddress {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+}
+
+// IMPLEMENTATION
+
+impl Endpoint {
+    pub fn tcp(host: impl Into<String>, port: u16) -> Self {
+        Self::Tcp(NetAddress::new(host, port))
+    }
+
+    pub fn websocket(host: impl Into<String>, port: u16) -> Self {
+        Self::WebSocket(NetAddress::new(host, port))
+    }
+
+    pub fn unix(path: impl Into<PathBuf>) -> Self {
+        Self::Unix(path.into())
+    }
+
+    /// same endpoint with TLS, unix sockets stay as they are
+    pub fn with_tls(mut self) -> Self {
+        if let Some(address) = self.address_mut() {
+            address.tls = true;
+        }
+        self
+    }
+
+    /// same endpoint on another port, e.g. the one a server actually bound to
+    pub fn with_port(mut self, port: u16) -> Self {
+        if let Some(address) = self.address_mut() {
+            address.port = port;
+        }
+        self
+    }
+
+    /// host and port, None for unix sockets
+    pub fn address(&self) -> Option<&NetAddress> {
+        match self {
+            Self::Tcp(address) | Self::WebSocket(address) => Some(address),
+            Self::Unix(_) => None,
+        }
+    }
+
+    fn address_mut(&mut self) -> Option<&mut NetAddress> {
+        match self {
+            Self::Tcp(address) | Self::WebSocket(address) => Some(address),
+            Self::Unix(_) => None,
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.address().is_some_and(|address| address.tls)
+    }
+
+    pub fn is_websocket(&self) -> bool {
+        matches!(self, Self::WebSocket(_))
+    }
+
+    fn scheme(&self) -> &'static str {
+        match (self, self.is_tls()) {
+            (Self::Tcp(_), false) => EDORAS_SCHEME,
+            (Self::Tcp(_), true) => EDORAS_TLS_SCHEME,
+            (Self::WebSocket(_), false) => WS_SCHEME,
+            (Self::WebSocket(_), true) => WSS_SCHEME,
+            (Self::Unix(_), _) => UNIX_SCHEME,
+        }
+    }
+}
+
+impl NetAddress {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            tls: false,
+        }
+    }
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Self::tcp(HOST, PORT)
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = EndpointError;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        let Some((scheme, rest)) = address.split_once("://") else {
+            return Err(EndpointError::MissingScheme(address.to_string()));
+        };
+
+        let (websocket, tls, default_port) = match scheme {
+            EDORAS_SCHEME => (false, false, PORT),
+            EDORAS_TLS_SCHEME => (false, true, PORT),
+            WS_SCHEME => (true, false, WS_PORT),
+            WSS_SCHEME => (true, true, WS_PORT),
+            UNIX_SCHEME if rest.is_empty() => return Err(EndpointError::MissingPath),
+            UNIX_SCHEME => return Ok(Self::unix(rest)),
+            _ => return Err(EndpointError::UnknownScheme(scheme.to_string())),
+        };
+
+        let rest = rest.trim_end_matches('/');
+        if let Some(pos) = rest.find('/') {
+            return Err(EndpointError::UnexpectedPath(rest[pos..].to_string()));
+        }
+
+        let (host, port) = split_host_port(rest)?;
+        if host.is_empty() {
+            return Err(EndpointError::MissingHost);
+        }
+
+        let port = port.unwrap_or(default_port);
+        let endpoint = match websocket {
+            true => Self::websocket(host, port),
+            false => Self::tcp(host, port),
+        };
+
+        Ok(match tls {
+            true => endpoint.with_tls(),
+            false => endpoint,
+        })
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(address) | Self::WebSocket(address) => {
+                write!(f, "{}://{}", self.scheme(), address)
+            }
+            Self::Unix(path) => write!(f, "{}://{}", self.scheme(), path.display()),
+        }
+    }
+}
+
+impl Display for NetAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // ipv6 addresses need brackets to tell them apart from the port
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// splits `host`, `host:port`, `[ipv6]` or `[ipv6]:port`, an ipv6 address without brackets is
+/// rejected because its last group could be taken for the port
+fn split_host_port(address: &str) -> Result<(&str, Option<u16>), EndpointError> {
+    let (host, port) = match address.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => match port.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(EndpointError::InvalidPort(port.to_string())),
+            },
+            None => return Err(EndpointError::MissingHost),
+        },
+        None => match address.rsplit_once(':') {
+            Some((host, _)) if host.contains(':') => {
+                return Err(EndpointError::InvalidHost(address.to_string()))
+            }
+            Some((host, port)) => (host, Some(port)),
+            None => (address, None),
+        },
+    };
+
+    let port = match port {
+        Some(port) => Some(
+            port.parse()
+                .map_err(|_| EndpointError::InvalidPort(port.to_string()))?,
+        ),
+        None => None,
+    };
+
+    Ok((host, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_parsed() {
+        let cases = [
+            ("edoras://localhost:4000", Endpoint::tcp("localhost", 4000)),
+            ("edoras://localhost", Endpoint::tcp("localhost", PORT)),
+            ("edoras://localhost:0", Endpoint::tcp("localhost", 0)),
+            ("edoras://localhost/", Endpoint::tcp("localhost", PORT)),
+            (
+                "edoras+tls://example.com:4000",
+                Endpoint::tcp("example.com", 4000).with_tls(),
+            ),
+            ("ws://127.0.0.1", Endpoint::websocket("127.0.0.1", WS_PORT)),
+            (
+                "wss://127.0.0.1:443",
+                Endpoint::websocket("127.0.0.1", 443).with_tls(),
+            ),
+            ("edoras://[::1]:4000", Endpoint::tcp("::1", 4000)),
+            ("edoras://[::1]", Endpoint::tcp("::1", PORT)),
+            (
+                "unix:///tmp/edoras.sock",
+                Endpoint::unix("/tmp/edoras.sock"),
+            ),
+        ];
+
+        for (address, endpoint) in cases {
+            assert_eq!(address.parse::<Endpoint>(), Ok(endpoint), "{}", address);
+        }
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        let cases = [
+            (
+                "localhost:4000",
+                EndpointError::MissingScheme("localhost:4000".into()),
+            ),
+            (
+                "http://localhost",
+                EndpointError::UnknownScheme("http".into()),
+            ),
+            ("edoras://:4000", EndpointError::MissingHost),
+            ("unix://", EndpointError::MissingPath),
+            ("edoras://localhost:", EndpointError::InvalidPort("".into())),
+            (
+                "edoras://localhost:65536",
+                EndpointError::InvalidPort("65536".into()),
+            ),
+            (
+                "edoras://[::1]4000",
+                EndpointError::InvalidPort("4000".into()),
+            ),
+            (
+                "edoras://::1:4000",
+                EndpointError::InvalidHost("::1:4000".into()),
+            ),
+            ("edoras://::1", EndpointError::InvalidHost("::1".into())),
+            (
+                "ws://localhost/chat",
+                EndpointError::UnexpectedPath("/chat".into()),
+            ),
+            (
+                "edoras://localhost:4000/a/b",
+                EndpointError::UnexpectedPath("/a/b".into()),
+            ),
+        ];
+
+        for (address, error) in cases {
+            assert_eq!(address.parse::<Endpoint>(), Err(error), "{}", address);
+        }
+    }
+
+    #[test]
+    fn addresses_round_trip_through_display() {
+        for address in [
+            "edoras://localhost:4000",
+            "edoras+tls://[::1]:4000",
+            "wss://example.com:443",
+            "unix:///tmp/edoras.sock",
+        ] {
+            let endpoint: Endpoint = address.parse().unwrap();
+            assert_eq!(endpoint.to_string(), address);
+        }
+    }
+}
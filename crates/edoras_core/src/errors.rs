@@ -1,29 +1,236 @@
+use crate::message::MessageType;
 use std::fmt::Display;
 use std::io::Error as IoError;
 
+pub type ErrorCodeValue = u16;
+
+const UNKNOWN: ErrorCodeValue = 0;
+const MALFORMED_MESSAGE: ErrorCodeValue = 1;
+const UNEXPECTED_MESSAGE: ErrorCodeValue = 2;
+const UNSUPPORTED_MESSAGE: ErrorCodeValue = 3;
+const INCOMPATIBLE_VERSION: ErrorCodeValue = 4;
+const RATE_LIMITED: ErrorCodeValue = 5;
+
+const ALREADY_AUTHENTICATED: ErrorCodeValue = 100;
+const USERNAME_TAKEN: ErrorCodeValue = 101;
+const USER_NOT_FOUND: ErrorCodeValue = 102;
+
 #[derive(Debug)]
 pub enum MessageError {
     UnknownError,
     ReadError(IoError),
     WriteError(IoError),
     InvalidMessage(Vec<u8>),
+    ConnectionClosed,
+    TooManyFields(u32),
+    FieldTooLarge(u32),
+    FrameTooLarge(usize),
+    UnknownType(u8),
+    InvalidFlags(u8),
+    MissingChecksum,
+    ChecksumMismatch(u32, u32), // expected, actual
+    InvalidCompression,
+    InvalidStreamedField(u32),
+    InvalidVarint,
+    UnexpectedMessage(MessageType),
+    MalformedMessage(MessageType),
+    IncompatibleVersion(u16),
 }
 
+impl Display for MessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageError::ReadError(err) => write!(f, "Failed to read from stream: {}", err),
+            MessageError::WriteError(err) => write!(f, "Failed to write to stream: {}", err),
+            MessageError::InvalidMessage(msg) => write!(f, "Invalid message: {:x?}", msg),
+            MessageError::ConnectionClosed => write!(f, "Connection closed by peer"),
+            MessageError::TooManyFields(count) => write!(f, "Too many fields: {}", count),
+            MessageError::FieldTooLarge(length) => write!(f, "Field too large: {} bytes", length),
+            MessageError::FrameTooLarge(size) => write!(f, "Frame too large: {} bytes", size),
+            MessageError::UnknownType(code) => write!(f, "Unknown message type code: {:#x}", code),
+            MessageError::InvalidFlags(flags) => write!(f, "Invalid frame flags: {:#010b}", flags),
+            MessageError::MissingChecksum => write!(f, "Frame is missing its checksum"),
+            MessageError::ChecksumMismatch(expected, actual) => write!(
+                f,
+                "Checksum mismatch: expected {:#010x}, got {:#010x}",
+                expected, actual
+            ),
+            MessageError::InvalidCompression => write!(f, "Failed to decompress frame"),
+            MessageError::InvalidStreamedField(length) => {
+                write!(f, "Invalid streamed field of {} bytes", length)
+            }
+            MessageError::InvalidVarint => write!(f, "Invalid varint length"),
+            MessageError::UnexpectedMessage(mtype) => write!(f, "Unexpected {:?} message", mtype),
+            MessageError::MalformedMessage(mtype) => write!(f, "Malformed {:?} message", mtype),
+            MessageError::IncompatibleVersion(version) => {
+                write!(f, "Incompatible protocol version: {}", version)
+            }
+            _ => write!(f, "Unknown error"),
+        }
+    }
+}
+
+impl std::error::Error for MessageError {}
+
 impl MessageError {
-    pub fn to_string(&self) -> String {
+    /// true if only a single frame is broken, the decoder skips it and stays usable
+    ///
+    /// with checksums negotiated such a frame was most likely corrupted on the way, e.g. a
+    /// flipped length byte is reported before the checksum of the frame is reached
+    pub fn is_frame_error(&self) -> bool {
+        matches!(
+            self,
+            MessageError::InvalidMessage(_)
+                | MessageError::TooManyFields(_)
+                | MessageError::FieldTooLarge(_)
+                | MessageError::FrameTooLarge(_)
+                | MessageError::UnknownType(_)
+                | MessageError::InvalidFlags(_)
+                | MessageError::MissingChecksum
+                | MessageError::ChecksumMismatch(_, _)
+                | MessageError::InvalidCompression
+                | MessageError::InvalidStreamedField(_)
+                | MessageError::InvalidVarint
+        )
+    }
+}
+
+/// why a frame was not queued for the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    Closed,
+    /// the queue is full and its policy is [`OverflowPolicy::Disconnect`](crate::OverflowPolicy)
+    Overflow,
+}
+
+impl Display for QueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            MessageError::ReadError(err) => format!("Failed to read from stream: {}", err),
-            MessageError::WriteError(err) => format!("Failed to write to stream: {}", err),
-            MessageError::InvalidMessage(msg) => format!("Invalid message: {:x?}", msg),
-            _ => String::from("Unknown error"),
+            QueueError::Closed => write!(f, "Outbound queue is closed"),
+            QueueError::Overflow => write!(f, "Outbound queue overflowed"),
         }
     }
 }
 
-impl Display for MessageError {
+impl std::error::Error for QueueError {}
+
+impl From<QueueError> for MessageError {
+    fn from(err: QueueError) -> Self {
+        MessageError::WriteError(IoError::new(std::io::ErrorKind::BrokenPipe, err))
+    }
+}
+
+/// reason an address could not be parsed as an [`Endpoint`](crate::Endpoint)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EndpointError {
+    MissingScheme(String),
+    UnknownScheme(String),
+    MissingHost,
+    MissingPath,
+    InvalidHost(String),
+    UnexpectedPath(String),
+    InvalidPort(String),
+}
+
+impl Display for EndpointError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        match self {
+            EndpointError::MissingScheme(address) => {
+                write!(f, "Address without scheme: {}", address)
+            }
+            EndpointError::UnknownScheme(scheme) => write!(f, "Unknown scheme: {}", scheme),
+            EndpointError::MissingHost => write!(f, "Address without host"),
+            EndpointError::MissingPath => write!(f, "Unix socket address without path"),
+            EndpointError::InvalidHost(host) => write!(f, "Invalid host: {}", host),
+            EndpointError::UnexpectedPath(path) => {
+                write!(f, "Only unix socket addresses have a path: {}", path)
+            }
+            EndpointError::InvalidPort(port) => write!(f, "Invalid port: {}", port),
+        }
     }
 }
 
-impl std::error::Error for MessageError {}
+impl std::error::Error for EndpointError {}
+
+/// machine-readable reason sent in an Error frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    // General
+    Unknown,
+    MalformedMessage,
+    UnexpectedMessage,
+    UnsupportedMessage,
+    IncompatibleVersion,
+    RateLimited,
+
+    // Auth
+    AlreadyAuthenticated,
+    UsernameTaken,
+    UserNotFound,
+
+    /// a code this implementation does not know yet
+    Other(ErrorCodeValue),
+}
+
+impl ErrorCode {
+    pub fn from_code(code: ErrorCodeValue) -> Self {
+        match code {
+            UNKNOWN => Self::Unknown,
+            MALFORMED_MESSAGE => Self::MalformedMessage,
+            UNEXPECTED_MESSAGE => Self::UnexpectedMessage,
+            UNSUPPORTED_MESSAGE => Self::UnsupportedMessage,
+            INCOMPATIBLE_VERSION => Self::IncompatibleVersion,
+            RATE_LIMITED => Self::RateLimited,
+            ALREADY_AUTHENTICATED => Self::AlreadyAuthenticated,
+            USERNAME_TAKEN => Self::UsernameTaken,
+            USER_NOT_FOUND => Self::UserNotFound,
+            code => Self::Other(code),
+        }
+    }
+
+    pub fn to_code(self) -> ErrorCodeValue {
+        match self {
+            Self::Unknown => UNKNOWN,
+            Self::MalformedMessage => MALFORMED_MESSAGE,
+            Self::UnexpectedMessage => UNEXPECTED_MESSAGE,
+            Self::UnsupportedMessage => UNSUPPORTED_MESSAGE,
+            Self::IncompatibleVersion => INCOMPATIBLE_VERSION,
+            Self::RateLimited => RATE_LIMITED,
+            Self::AlreadyAuthenticated => ALREADY_AUTHENTICATED,
+            Self::UsernameTaken => USERNAME_TAKEN,
+            Self::UserNotFound => USER_NOT_FOUND,
+            Self::Other(code) => code,
+        }
+    }
+}
+
+impl From<&MessageError> for ErrorCode {
+    fn from(err: &MessageError) -> Self {
+        match err {
+            MessageError::TooManyFields(_)
+            | MessageError::FieldTooLarge(_)
+            | MessageError::FrameTooLarge(_)
+            | MessageError::InvalidFlags(_)
+            | MessageError::MissingChecksum
+            | MessageError::ChecksumMismatch(_, _)
+            | MessageError::InvalidCompression
+            | MessageError::InvalidStreamedField(_)
+            | MessageError::InvalidVarint
+            | MessageError::InvalidMessage(_)
+            | MessageError::MalformedMessage(_) => Self::MalformedMessage,
+            MessageError::UnknownType(_) => Self::UnsupportedMessage,
+            MessageError::UnexpectedMessage(_) => Self::UnexpectedMessage,
+            MessageError::IncompatibleVersion(_) => Self::IncompatibleVersion,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Other(code) => write!(f, "Error code {}", code),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
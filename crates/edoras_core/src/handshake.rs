@@ -0,0 +1,209 @@
+use crate::errors::MessageError;
+use crate::message::MessageType;
+use crate::payload::{read_field, Payload, PayloadField};
+use bytes::Bytes;
+use std::ops::{BitAnd, BitOr};
+
+pub type ProtocolVersion = u16;
+
+/// newest protocol version spoken by this implementation
+pub const PROTOCOL_VERSION: ProtocolVersion = 2;
+/// oldest protocol version still supported by this implementation
+pub const MIN_PROTOCOL_VERSION: ProtocolVersion = 1;
+
+/// optional protocol features a peer supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Capabilities(u32);
+
+/// first frame sent by the client on a new connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    protocol_version: ProtocolVersion,
+    min_protocol_version: ProtocolVersion,
+    software_version: String,
+    capabilities: Capabilities,
+}
+
+/// reply of the server to an accepted [`Hello`], carries the negotiated settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Welcome {
+    protocol_version: ProtocolVersion,
+    software_version: String,
+    capabilities: Capabilities,
+}
+
+// IMPLEMENTATION
+
+impl Capabilities {
+    pub const CHANNELS: Self = Self(1 << 5);
+    pub const CHECKSUM: Self = Self(1 << 3);
+    pub const DEFLATE: Self = Self(1 << 0);
+    pub const ENCRYPTION: Self = Self(1 << 1);
+    pub const FILE_TRANSFER: Self = Self(1 << 2);
+    pub const ZSTD: Self = Self(1 << 4);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Hello {
+    pub fn new(software_version: impl Into<String>, capabilities: Capabilities) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            software_version: software_version.into(),
+            capabilities,
+        }
+    }
+
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    pub fn min_protocol_version(&self) -> ProtocolVersion {
+        self.min_protocol_version
+    }
+
+    pub fn software_version(&self) -> &str {
+        &self.software_version
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// picks the newest protocol version and the common capabilities of both peers
+    ///
+    /// fails with [`MessageError::IncompatibleVersion`] if the supported version ranges do not
+    /// overlap
+    pub fn negotiate(
+        &self,
+        software_version: impl Into<String>,
+        capabilities: Capabilities,
+    ) -> Result<Welcome, MessageError> {
+        let version = self.protocol_version.min(PROTOCOL_VERSION);
+        if version < self.min_protocol_version.max(MIN_PROTOCOL_VERSION) {
+            return Err(MessageError::IncompatibleVersion(self.protocol_version));
+        }
+
+        Ok(Welcome {
+            protocol_version: version,
+            software_version: software_version.into(),
+            capabilities: self.capabilities & capabilities,
+        })
+    }
+}
+
+impl Payload for Hello {
+    const MESSAGE_TYPE: MessageType = MessageType::Hello;
+
+    fn to_fields(&self) -> Vec<Bytes> {
+        vec![
+            self.protocol_version.to_field(),
+            self.min_protocol_version.to_field(),
+            self.software_version.to_field(),
+            self.capabilities.to_field(),
+        ]
+    }
+
+    fn from_fields(fields: &[&[u8]]) -> Result<Self, MessageError> {
+        let [protocol_version, min_protocol_version, software_version, capabilities] = fields
+        else {
+            return Err(MessageError::MalformedMessage(Self::MESSAGE_TYPE));
+        };
+
+        Ok(Self {
+            protocol_version: read_field(Self::MESSAGE_TYPE, protocol_version)?,
+            min_protocol_version: read_field(Self::MESSAGE_TYPE, min_protocol_version)?,
+            software_version: read_field(Self::MESSAGE_TYPE, software_version)?,
+            capabilities: read_field(Self::MESSAGE_TYPE, capabilities)?,
+        })
+    }
+}
+
+impl Welcome {
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    pub fn software_version(&self) -> &str {
+        &self.software_version
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+}
+
+impl Payload for Welcome {
+    const MESSAGE_TYPE: MessageType = MessageType::Welcome;
+
+    fn to_fields(&self) -> Vec<Bytes> {
+        vec![
+            self.protocol_version.to_field(),
+            self.software_version.to_field(),
+            self.capabilities.to_field(),
+        ]
+    }
+
+    /// fails with [`MessageError::IncompatibleVersion`] if the server picked a protocol version
+    /// this implementation does not support
+    fn from_fields(fields: &[&[u8]]) -> Result<Self, MessageError> {
+        let [protocol_version, software_version, capabilities] = fields else {
+            return Err(MessageError::MalformedMessage(Self::MESSAGE_TYPE));
+        };
+
+        let welcome = Self {
+            protocol_version: read_field(Self::MESSAGE_TYPE, protocol_version)?,
+            software_version: read_field(Self::MESSAGE_TYPE, software_version)?,
+            capabilities: read_field(Self::MESSAGE_TYPE, capabilities)?,
+        };
+
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&welcome.protocol_version) {
+            return Err(MessageError::IncompatibleVersion(welcome.protocol_version));
+        }
+
+        Ok(welcome)
+    }
+}
@@ -0,0 +1,94 @@
+use edoras_derive::Payload;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// probe of the peer, it has to answer with a [`Pong`] that echoes the nonce and the timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Payload)]
+#[payload(Ping)]
+pub struct Ping {
+    pub nonce: u64,
+    pub timestamp: u64, // microseconds since the unix epoch on the side of the sender
+}
+
+/// answer to a [`Ping`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Payload)]
+#[payload(Pong)]
+pub struct Pong {
+    pub nonce: u64,
+    pub timestamp: u64,
+}
+
+/// pings of a connection that were not answered yet and the round trip time of the last one
+/// that was
+#[derive(Debug)]
+pub struct Keepalive {
+    timeout: Duration,
+    next_nonce: u64,
+    outstanding: HashMap<u64, Instant>, // nonce -> sent
+    rtt: Option<Duration>,
+}
+
+// IMPLEMENTATION
+
+impl Ping {
+    pub fn new(nonce: u64) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Self {
+            nonce,
+            timestamp: timestamp.as_micros() as u64,
+        }
+    }
+
+    pub fn pong(&self) -> Pong {
+        Pong {
+            nonce: self.nonce,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+impl Keepalive {
+    /// the peer is considered dead once a ping is not answered within the timeout
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            next_nonce: 0,
+            outstanding: HashMap::new(),
+            rtt: None,
+        }
+    }
+
+    /// a new ping that stays outstanding until its pong arrives
+    pub fn ping(&mut self) -> Ping {
+        let ping = Ping::new(self.next_nonce);
+        self.next_nonce = self.next_nonce.wrapping_add(1);
+        self.outstanding.insert(ping.nonce, Instant::now());
+        ping
+    }
+
+    /// round trip time of the ping the pong answers, `None` for pongs of unknown pings
+    pub fn pong(&mut self, pong: &Pong) -> Option<Duration> {
+        let rtt = self.outstanding.remove(&pong.nonce)?.elapsed();
+        self.rtt = Some(rtt);
+        Some(rtt)
+    }
+
+    /// true if a ping was not answered within the timeout
+    pub fn timed_out(&self) -> bool {
+        self.outstanding
+            .values()
+            .any(|sent| sent.elapsed() > self.timeout)
+    }
+
+    /// round trip time of the last answered ping
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+}
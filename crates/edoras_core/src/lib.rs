@@ -1,8 +1,62 @@
+// allows the derive macros to refer to `::edoras_core` from inside of this crate
+extern crate self as edoras_core;
+
+mod capture;
+mod channel;
+mod chunk;
+mod codec;
+mod compression;
+mod disconnect;
+mod encoding;
+mod endpoint;
 mod errors;
+mod handshake;
+mod keepalive;
+mod limits;
 mod message;
+mod outbound;
+mod payload;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "ws")]
+mod ws;
 
-pub use errors::MessageError;
-pub use message::{Message, MessageBuilder, MessageType};
+/// shared buffer of message fields, see [`Message::data`]
+pub use bytes::Bytes;
+pub use capture::{
+    CaptureReader, CaptureRecord, CaptureSide, CaptureWriter, Direction, CAPTURE_EXTENSION,
+    CAPTURE_MAGIC,
+};
+pub use channel::{ChannelId, FrameScheduler, BULK_CHANNEL, CHAT_CHANNEL, CONTROL_CHANNEL};
+pub use chunk::{Chunk, ChunkStream, StreamId, StreamRouter, CHUNK_SIZE, STREAM_BUFFER};
+pub use codec::{Frame, MessageDecoder, MessageEncoder};
+pub use compression::{Compression, COMPRESSION_THRESHOLD};
+pub use disconnect::{Disconnect, DisconnectReason, DisconnectReasonValue};
+pub use edoras_derive::Payload;
+pub use encoding::{Encoding, COMPACT_PROTOCOL_VERSION};
+pub use endpoint::{Endpoint, NetAddress};
+pub use errors::{EndpointError, ErrorCode, ErrorCodeValue, MessageError, QueueError};
+pub use handshake::{
+    Capabilities, Hello, ProtocolVersion, Welcome, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+pub use keepalive::{Keepalive, Ping, Pong};
+pub use limits::DecodeLimits;
+pub use message::{
+    ExtensionCode, Message, MessageBuilder, MessageType, MessageTypeCode, RequestId,
+    EXTENSION_RANGE,
+};
+pub use outbound::{OutboundQueue, OverflowPolicy, QueueMetrics, QUEUE_CAPACITY};
+#[doc(hidden)]
+pub use payload::read_field;
+pub use payload::{ErrorReply, LoginRequest, Payload, PayloadField, RegisterRequest};
+#[cfg(feature = "ws")]
+pub use ws::WsStream;
 
+/// host of the default [`Endpoint`]
 pub const HOST: &str = "127.0.0.1";
+/// port of TCP endpoints that leave it out
 pub const PORT: u16 = 42428;
+/// port of WebSocket endpoints that leave it out
+pub const WS_PORT: u16 = 42429;
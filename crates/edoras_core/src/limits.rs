@@ -0,0 +1,69 @@
+use crate::message::BaseLength;
+
+const DEFAULT_MAX_FIELDS: BaseLength = 256;
+const DEFAULT_MAX_FIELD_SIZE: BaseLength = 1024 * 1024; // 1 MiB
+const DEFAULT_MAX_FRAME_SIZE: usize = 4 * 1024 * 1024; // 4 MiB
+
+/// upper bounds for frames accepted by the decoder
+///
+/// the limits are checked as soon as the corresponding length is read, so nothing is allocated
+/// for frames that exceed them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    max_fields: BaseLength,
+    max_field_size: BaseLength,
+    max_frame_size: usize,
+}
+
+// IMPLEMENTATION
+
+impl DecodeLimits {
+    pub const fn new(
+        max_fields: BaseLength,
+        max_field_size: BaseLength,
+        max_frame_size: usize,
+    ) -> Self {
+        Self {
+            max_fields,
+            max_field_size,
+            max_frame_size,
+        }
+    }
+
+    pub const fn with_max_fields(mut self, max_fields: BaseLength) -> Self {
+        self.max_fields = max_fields;
+        self
+    }
+
+    pub const fn with_max_field_size(mut self, max_field_size: BaseLength) -> Self {
+        self.max_field_size = max_field_size;
+        self
+    }
+
+    pub const fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    pub fn max_fields(&self) -> BaseLength {
+        self.max_fields
+    }
+
+    pub fn max_field_size(&self) -> BaseLength {
+        self.max_field_size
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_MAX_FIELDS,
+            DEFAULT_MAX_FIELD_SIZE,
+            DEFAULT_MAX_FRAME_SIZE,
+        )
+    }
+}
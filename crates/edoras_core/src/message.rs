@@ -1,50 +1,93 @@
+use crate::channel::{ChannelId, CONTROL_CHANNEL};
+use crate::chunk::StreamId;
+use crate::codec::{decode_frame, Decoded, DecoderConfig, MessageEncoder};
 use crate::errors::MessageError;
-use async_std::net::TcpStream;
-use futures::{AsyncReadExt, AsyncWriteExt};
-
-type MessageTypeCode = u8;
-type BaseLength = u32;
-
-const HEADER_SIZE: usize = 4;
-const MESSAGE_TYPE_SIZE: usize = size_of::<MessageTypeCode>();
-const BASE_LENGTH_SIZE: usize = size_of::<BaseLength>();
+use crate::limits::DecodeLimits;
+use bytes::{Bytes, BytesMut};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite};
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+
+pub type MessageTypeCode = u8;
+pub type RequestId = u32;
+pub(crate) type BaseLength = u32;
+pub(crate) type FrameFlags = u8;
+
+pub(crate) const HEADER_SIZE: usize = 4;
+pub(crate) const MESSAGE_TYPE_SIZE: usize = size_of::<MessageTypeCode>();
+pub(crate) const BASE_LENGTH_SIZE: usize = size_of::<BaseLength>();
+pub(crate) const FRAME_FLAGS_SIZE: usize = size_of::<FrameFlags>();
+pub(crate) const REQUEST_ID_SIZE: usize = size_of::<RequestId>();
+pub(crate) const CHANNEL_ID_SIZE: usize = size_of::<ChannelId>();
+pub(crate) const STREAM_ID_SIZE: usize = size_of::<StreamId>();
+
+/// set in the length of a field whose data follows in [`MessageType::Chunk`] frames, the field
+/// itself only carries the stream id
+pub(crate) const STREAMED_FIELD: BaseLength = 1 << 31;
 
 pub const HEADER: [u8; HEADER_SIZE] = [0x1, 0x3c, 0x21, 0x3e];
 
 const EMPTY: MessageTypeCode = 0x00;
+const HELLO: MessageTypeCode = 0x5; // ENQ
+const WELCOME: MessageTypeCode = 0x2; // STX
 const PING: MessageTypeCode = 0x3c; // >
 const PONG: MessageTypeCode = 0x3e; // <
 const CHECK: MessageTypeCode = 0x3f; // ?
 const OKAY: MessageTypeCode = 0x6; // ACK
 const ERROR: MessageTypeCode = 0x18; // CAN
 const DISCONNECT: MessageTypeCode = 0x1b; // ESC
+const CHUNK: MessageTypeCode = 0x17; // ETB
 
 const LOGIN: MessageTypeCode = 0x2a; // *
 const REGISTER: MessageTypeCode = 0x2b; // +
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// codes reserved for protocol extensions, see [`MessageType::Extension`]
+pub const EXTENSION_RANGE: RangeInclusive<MessageTypeCode> = 0x80..=0xff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MessageType {
     // General
     Empty,
+    Hello,
+    Welcome,
     Ping,
     Pong,
     Check,
     Okay,
     Error,
     Disconnect,
+    Chunk,
 
     // Auth
     Login,
     Register,
+
+    /// a message type outside of the core protocol with a code in [`EXTENSION_RANGE`]
+    ///
+    /// peers that do not know an extension decode it like any other message and are free to
+    /// ignore or reject it, so newer clients stay compatible with older servers
+    Extension(ExtensionCode),
 }
 
+/// code of a [`MessageType::Extension`], it always lies in [`EXTENSION_RANGE`], so an extension
+/// can not be mistaken for a message type of the core protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "MessageTypeCode", into = "MessageTypeCode")
+)]
+pub struct ExtensionCode(MessageTypeCode);
+
 #[derive(Debug, Clone)]
-struct MessageField {
-    length: BaseLength,
-    data: Vec<u8>,
+pub(crate) struct MessageField {
+    pub(crate) length: BaseLength,
+    pub(crate) data: Bytes,
+    pub(crate) streamed: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 struct MessageBody {
     count: BaseLength,
     fields: Vec<MessageField>,
@@ -54,57 +97,140 @@ struct MessageBody {
 #[derive(Debug, Clone)]
 pub struct Message {
     mtype: MessageType,
+    request_id: Option<RequestId>,
+    channel: ChannelId,
     body: MessageBody,
 }
 
 pub struct MessageBuilder {
     mtype: MessageType,
+    request_id: Option<RequestId>,
+    channel: ChannelId,
     body: MessageBody,
 }
 
 // IMPLEMENTATION
 
 impl MessageType {
-    fn from_code(code: MessageTypeCode) -> Self {
+    pub fn from_code(code: MessageTypeCode) -> Result<Self, MessageError> {
         match code {
-            EMPTY => Self::Empty,
-            PING => Self::Ping,
-            PONG => Self::Pong,
-            CHECK => Self::Check,
-            OKAY => Self::Okay,
-            ERROR => Self::Error,
-            DISCONNECT => Self::Disconnect,
-            LOGIN => Self::Login,
-            REGISTER => Self::Register,
-            _ => panic!("Unknown message type code: {}", code),
+            EMPTY => Ok(Self::Empty),
+            HELLO => Ok(Self::Hello),
+            WELCOME => Ok(Self::Welcome),
+            PING => Ok(Self::Ping),
+            PONG => Ok(Self::Pong),
+            CHECK => Ok(Self::Check),
+            OKAY => Ok(Self::Okay),
+            ERROR => Ok(Self::Error),
+            DISCONNECT => Ok(Self::Disconnect),
+            CHUNK => Ok(Self::Chunk),
+            LOGIN => Ok(Self::Login),
+            REGISTER => Ok(Self::Register),
+            code => ExtensionCode::new(code)
+                .map(Self::Extension)
+                .ok_or(MessageError::UnknownType(code)),
         }
     }
 
-    fn to_code(&self) -> MessageTypeCode {
+    /// extension message type of the code, `None` for codes outside of [`EXTENSION_RANGE`]
+    pub fn extension(code: MessageTypeCode) -> Option<Self> {
+        ExtensionCode::new(code).map(Self::Extension)
+    }
+
+    pub fn to_code(self) -> MessageTypeCode {
         match self {
             Self::Empty => EMPTY,
+            Self::Hello => HELLO,
+            Self::Welcome => WELCOME,
             Self::Ping => PING,
             Self::Pong => PONG,
             Self::Check => CHECK,
             Self::Okay => OKAY,
             Self::Error => ERROR,
             Self::Disconnect => DISCONNECT,
+            Self::Chunk => CHUNK,
             Self::Login => LOGIN,
             Self::Register => REGISTER,
+            Self::Extension(code) => code.get(),
         }
     }
+
+    pub fn is_extension(&self) -> bool {
+        matches!(self, Self::Extension(_))
+    }
+}
+
+impl ExtensionCode {
+    /// `None` for codes outside of [`EXTENSION_RANGE`], they belong to the core protocol
+    pub fn new(code: MessageTypeCode) -> Option<Self> {
+        EXTENSION_RANGE.contains(&code).then_some(Self(code))
+    }
+
+    pub fn get(self) -> MessageTypeCode {
+        self.0
+    }
+}
+
+impl TryFrom<MessageTypeCode> for ExtensionCode {
+    type Error = MessageError;
+
+    fn try_from(code: MessageTypeCode) -> Result<Self, Self::Error> {
+        Self::new(code).ok_or(MessageError::UnknownType(code))
+    }
+}
+
+impl From<ExtensionCode> for MessageTypeCode {
+    fn from(code: ExtensionCode) -> Self {
+        code.get()
+    }
+}
+
+impl std::fmt::LowerHex for ExtensionCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::LowerHex::fmt(&self.0, f)
+    }
 }
 
 impl MessageField {
-    fn new(data: Vec<u8>) -> Self {
+    pub(crate) fn new(data: Bytes) -> Self {
         Self {
             length: data.len() as BaseLength,
             data,
+            streamed: false,
         }
     }
 
-    fn to_string(&self) -> String {
-        String::from_utf8_lossy(&self.data).to_string()
+    pub(crate) fn streamed(stream_id: StreamId) -> Self {
+        Self {
+            streamed: true,
+            ..Self::new(Bytes::copy_from_slice(&stream_id.to_le_bytes()))
+        }
+    }
+
+    pub(crate) fn stream_id(&self) -> Option<StreamId> {
+        match self.streamed {
+            true => self
+                .data
+                .as_ref()
+                .try_into()
+                .ok()
+                .map(StreamId::from_le_bytes),
+            false => None,
+        }
+    }
+
+    /// the length as it is sent, including the [`STREAMED_FIELD`] marker
+    pub(crate) fn wire_length(&self) -> BaseLength {
+        match self.streamed {
+            true => self.length | STREAMED_FIELD,
+            false => self.length,
+        }
+    }
+}
+
+impl Display for MessageField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.data))
     }
 }
 
@@ -120,33 +246,40 @@ impl MessageBody {
     }
 }
 
-impl Default for MessageBody {
-    fn default() -> Self {
-        Self {
-            count: 0,
-            fields: vec![],
-        }
-    }
-}
-
 impl Message {
-    pub const DISCONNECT_MESSAGE: Message = Message {
-        mtype: MessageType::Disconnect,
-        body: MessageBody {
-            count: 0,
-            fields: vec![],
-        },
-    };
-
     pub fn mtype(&self) -> MessageType {
         self.mtype
     }
 
+    /// id of the request this message belongs to, replies carry the id of their request
+    pub fn request_id(&self) -> Option<RequestId> {
+        self.request_id
+    }
+
+    /// logical flow the message belongs to, see [`crate::FrameScheduler`]
+    pub fn channel(&self) -> ChannelId {
+        self.channel
+    }
+
     pub fn field_count(&self) -> BaseLength {
         self.body.count
     }
 
-    pub fn data(&self) -> Vec<Vec<u8>> {
+    pub(crate) fn fields(&self) -> &[MessageField] {
+        &self.body.fields
+    }
+
+    /// ids of the streamed fields in the order of the fields, see [`crate::StreamRouter`]
+    pub fn stream_ids(&self) -> Vec<StreamId> {
+        self.body
+            .fields
+            .iter()
+            .filter_map(MessageField::stream_id)
+            .collect()
+    }
+
+    /// the fields of the message, they share the memory of the message
+    pub fn data(&self) -> Vec<Bytes> {
         self.body
             .fields
             .iter()
@@ -158,116 +291,49 @@ impl Message {
         self.body
             .fields
             .iter()
-            .map(|field| field.data.as_slice())
+            .map(|field| field.data.as_ref())
             .collect()
     }
 
-    pub async fn peek_for_header(stream: &mut TcpStream) -> bool {
-        let mut buf = [0u8; HEADER_SIZE];
-
-        if stream.peek(&mut buf).await.is_err() {
-            return false;
-        }
-
-        buf == HEADER
+    pub async fn send<S>(&self, stream: &mut S) -> Result<(), MessageError>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        MessageEncoder::new().send(self, stream).await
     }
 
-    pub async fn send(&self, stream: &mut TcpStream) -> Result<(), MessageError> {
-        let mut buf: Vec<u8> = vec![];
-        buf.extend_from_slice(&HEADER);
-        buf.extend_from_slice(&self.mtype.to_code().to_le_bytes());
-
-        buf.extend_from_slice(&self.body.count.to_le_bytes());
-        for field in &self.body.fields {
-            buf.extend_from_slice(&field.length.to_le_bytes());
-            buf.extend_from_slice(&field.data);
-        }
-
-        tracing::debug!("Sending message | {:x?}", buf);
-
-        match stream.write_all(&buf).await {
-            Ok(_) => Ok(()),
-            Err(err) => Err(MessageError::WriteError(err)),
-        }
+    /// reads exactly one message from the stream using the default limits
+    pub async fn recv<S>(stream: &mut S) -> Result<Message, MessageError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        Self::recv_with_limits(stream, DecodeLimits::default()).await
     }
 
-    pub async fn recv(stream: &mut TcpStream) -> Result<Message, MessageError> {
-        let mut buf = [0u8; HEADER_SIZE];
-        if let Err(e) = stream.read_exact(&mut buf).await {
-            return Err(MessageError::ReadError(e));
-        }
-
-        if buf != HEADER {
-            return Err(MessageError::InvalidMessage(buf.to_vec()));
-        }
-
-        tracing::debug!("Header is valid | {:x?}", buf);
-
-        let mut buf = [0u8; MESSAGE_TYPE_SIZE];
-        if let Err(e) = stream.read_exact(&mut buf).await {
-            return Err(MessageError::ReadError(e));
-        }
-
-        let mut mtype: MessageTypeCode = 0;
-        for i in 0..MESSAGE_TYPE_SIZE {
-            mtype |= (buf[i] as MessageTypeCode) << (i * 8);
-        }
-
-        let mut builder = MessageBuilder::new().with_type(MessageType::from_code(mtype));
-
-        tracing::debug!(
-            "Message type is valid | {:?}",
-            MessageType::from_code(mtype)
-        );
-
-        let mut buf = [0u8; BASE_LENGTH_SIZE];
-        if let Err(e) = stream.read_exact(&mut buf).await {
-            return Err(MessageError::ReadError(e));
-        }
-
-        let mut count: BaseLength = 0;
-        for i in 0..BASE_LENGTH_SIZE {
-            count |= (buf[i] as BaseLength) << (i * 8);
-        }
-
-        tracing::debug!(
-            "Fields length is valid | {}, Raw: {:?}",
-            count,
-            buf.to_vec()
-        );
-
-        if count == 0 {
-            return Ok(builder.build());
-        }
-
-        for _ in 0..count {
-            let mut buf = [0u8; BASE_LENGTH_SIZE];
-            if let Err(e) = stream.read_exact(&mut buf).await {
-                return Err(MessageError::ReadError(e));
-            }
-
-            let mut length: BaseLength = 0;
-            for i in 0..BASE_LENGTH_SIZE {
-                length |= (buf[i] as BaseLength) << (i * 8);
+    /// reads exactly one message from the stream
+    pub async fn recv_with_limits<S>(
+        stream: &mut S,
+        limits: DecodeLimits,
+    ) -> Result<Message, MessageError>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let config = DecoderConfig::with_limits(limits);
+        let mut buf = BytesMut::new();
+
+        loop {
+            match decode_frame(&mut buf, &config)? {
+                Decoded::Frame(message, _) => return message,
+                Decoded::Incomplete(needed) => {
+                    let read = buf.len();
+                    buf.resize(needed, 0);
+
+                    if let Err(e) = stream.read_exact(&mut buf[read..]).await {
+                        return Err(MessageError::ReadError(e));
+                    }
+                }
             }
-
-            tracing::debug!(
-                "Field length is valid | {}, Raw: {:?}",
-                length,
-                buf.to_vec()
-            );
-
-            let mut data = vec![0u8; length as usize];
-            if let Err(e) = stream.read_exact(&mut data).await {
-                return Err(MessageError::ReadError(e));
-            }
-
-            tracing::debug!("Field data is valid | {:x?}", data);
-
-            builder = builder.with_field(data);
         }
-
-        Ok(builder.build())
     }
 }
 
@@ -275,6 +341,8 @@ impl MessageBuilder {
     pub fn new() -> Self {
         Self {
             mtype: MessageType::Empty,
+            request_id: None,
+            channel: CONTROL_CHANNEL,
             body: MessageBody::default(),
         }
     }
@@ -284,21 +352,82 @@ impl MessageBuilder {
         self
     }
 
-    pub fn with_field(mut self, data: impl Into<Vec<u8>>) -> Self {
+    pub fn with_request_id(mut self, request_id: RequestId) -> Self {
+        self.request_id = Some(request_id);
+        self
+    }
+
+    /// copies the request id and the channel of the message this one is a reply to
+    pub fn with_reply_to(mut self, message: &Message) -> Self {
+        self.request_id = message.request_id();
+        self.channel = message.channel();
+        self
+    }
+
+    pub fn with_channel(mut self, channel: ChannelId) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    /// `Bytes` and `Vec<u8>` are taken over without copying them
+    pub fn with_field(mut self, data: impl Into<Bytes>) -> Self {
         self.body.add_field(MessageField::new(data.into()));
         self
     }
 
-    pub fn with_fields(mut self, fields: Vec<Vec<u8>>) -> Self {
-        self.body
-            .add_fields(fields.into_iter().map(MessageField::new).collect());
+    /// declares a field whose data is sent afterwards as [`crate::Chunk`]s of the stream
+    pub fn with_streamed_field(mut self, stream_id: StreamId) -> Self {
+        self.body.add_field(MessageField::streamed(stream_id));
+        self
+    }
+
+    pub(crate) fn with_message_fields(mut self, fields: Vec<MessageField>) -> Self {
+        self.body.add_fields(fields);
         self
     }
 
-    pub fn build(&self) -> Message {
+    pub fn with_fields<I, B>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<Bytes>,
+    {
+        self.body.add_fields(
+            fields
+                .into_iter()
+                .map(|data| MessageField::new(data.into()))
+                .collect(),
+        );
+        self
+    }
+
+    pub fn build(self) -> Message {
         Message {
             mtype: self.mtype,
-            body: self.body.clone(),
+            request_id: self.request_id,
+            channel: self.channel,
+            body: self.body,
+        }
+    }
+}
+
+impl Default for MessageBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_codes_stay_in_their_range() {
+        assert!(MessageType::extension(HELLO).is_none());
+        assert!(MessageType::extension(*EXTENSION_RANGE.start() - 1).is_none());
+
+        for code in [*EXTENSION_RANGE.start(), *EXTENSION_RANGE.end()] {
+            let mtype = MessageType::extension(code).unwrap();
+            assert_eq!(MessageType::from_code(mtype.to_code()).unwrap(), mtype);
         }
     }
 }
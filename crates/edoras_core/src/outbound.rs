@@ -0,0 +1,297 @@
+use crate::channel::{is_urgent, FrameScheduler};
+use crate::codec::Frame;
+use crate::errors::QueueError;
+use crate::message::Message;
+use std::future::poll_fn;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Poll, Waker};
+
+/// frames an [`OutboundQueue`] holds unless it is configured otherwise
+pub const QUEUE_CAPACITY: usize = 256;
+
+/// what happens to a frame that is pushed to a full [`OutboundQueue`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum OverflowPolicy {
+    /// the frame that was queued first is dropped to make room. Replies and the frames of
+    /// streamed fields are kept, the push fails like [`OverflowPolicy::Disconnect`] if only such
+    /// frames are queued
+    DropOldest,
+    /// the push fails, the peer is too slow to keep up and should be disconnected
+    #[default]
+    Disconnect,
+    /// the sender waits until the writer made room
+    Block,
+}
+
+/// counters of an [`OutboundQueue`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueMetrics {
+    pub depth: usize,     // frames that are queued right now
+    pub max_depth: usize, // highest depth so far
+    pub pushed: u64,
+    pub dropped: u64, // frames dropped to make room for newer ones
+    pub blocked: u64, // pushes that had to wait for room
+}
+
+/// bounded queue of the frames for one peer, drained by a single writer
+///
+/// the frames leave the queue in the order of a [`FrameScheduler`]. Ping, Pong and Disconnect
+/// always fit, so a connection can still be checked and closed gracefully when the queue is
+/// full. At most one Ping and one Pong are queued, a newer one replaces the older. Clones are
+/// handles of the same queue
+#[derive(Debug, Clone)]
+pub struct OutboundQueue {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    capacity: usize,
+    policy: OverflowPolicy,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    scheduler: FrameScheduler,
+    closed: bool,
+    writer: Option<Waker>,
+    blocked: Vec<Waker>, // pushes that wait for room
+    metrics: QueueMetrics,
+}
+
+// IMPLEMENTATION
+
+impl OutboundQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                capacity,
+                policy,
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.shared.policy
+    }
+
+    /// queues the frame of the message, a full queue is handled by the overflow policy
+    pub async fn push(&self, message: &Message, frame: Frame) -> Result<(), QueueError> {
+        let urgent = is_urgent(message.mtype());
+        let mut frame = Some(frame);
+        let mut waited = false;
+
+        poll_fn(|cx| {
+            let mut state = self.state();
+            if state.closed {
+                return Poll::Ready(Err(QueueError::Closed));
+            }
+
+            if !urgent && state.scheduler.len() >= self.shared.capacity {
+                match self.shared.policy {
+                    OverflowPolicy::DropOldest => match state.scheduler.drop_oldest() {
+                        Some(_) => state.metrics.dropped += 1,
+                        None => return Poll::Ready(Err(QueueError::Overflow)),
+                    },
+                    OverflowPolicy::Disconnect => return Poll::Ready(Err(QueueError::Overflow)),
+                    OverflowPolicy::Block => {
+                        if !waited {
+                            waited = true;
+                            state.metrics.blocked += 1;
+                        }
+                        state.blocked.push(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                }
+            }
+
+            if let Some(frame) = frame.take() {
+                state.scheduler.push(message, frame);
+            }
+            state.metrics.pushed += 1;
+            state.update_depth();
+            if let Some(writer) = state.writer.take() {
+                writer.wake();
+            }
+
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// next frame to write, `None` once the queue is closed and every frame was taken
+    pub async fn pop(&self) -> Option<Frame> {
+        poll_fn(|cx| {
+            let mut state = self.state();
+            if let Some(frame) = state.scheduler.pop() {
+                state.update_depth();
+                for waker in state.blocked.drain(..) {
+                    waker.wake();
+                }
+                return Poll::Ready(Some(frame));
+            }
+
+            if state.closed {
+                return Poll::Ready(None);
+            }
+
+            state.writer = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// refuses every further push, the frames that are queued are still handed to the writer
+    pub fn close(&self) {
+        let mut state = self.state();
+        state.closed = true;
+        if let Some(writer) = state.writer.take() {
+            writer.wake();
+        }
+        for waker in state.blocked.drain(..) {
+            waker.wake();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state().closed
+    }
+
+    pub fn len(&self) -> usize {
+        self.state().scheduler.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state().scheduler.is_empty()
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.state().metrics
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // the state stays consistent even if a holder of the lock panicked
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for OutboundQueue {
+    fn default() -> Self {
+        Self::new(QUEUE_CAPACITY, OverflowPolicy::default())
+    }
+}
+
+impl State {
+    fn update_depth(&mut self) {
+        self.metrics.depth = self.scheduler.len();
+        self.metrics.max_depth = self.metrics.max_depth.max(self.metrics.depth);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::MessageEncoder;
+    use crate::keepalive::Ping;
+    use crate::message::{MessageBuilder, MessageType};
+    use crate::payload::Payload;
+    use futures::FutureExt;
+    use std::pin::pin;
+
+    fn message(mtype: MessageType) -> Message {
+        MessageBuilder::new().with_type(mtype).build()
+    }
+
+    async fn push(queue: &OutboundQueue, message: &Message) -> Result<(), QueueError> {
+        queue
+            .push(message, MessageEncoder::new().encode(message))
+            .await
+    }
+
+    #[async_std::test]
+    async fn keepalives_fit_but_do_not_pile_up() {
+        let queue = OutboundQueue::new(1, OverflowPolicy::Block);
+        let data = MessageBuilder::new()
+            .with_type(MessageType::extension(0x80).unwrap())
+            .build();
+        push(&queue, &data).await.unwrap();
+
+        // a full queue still takes keepalives and the Disconnect without waiting
+        let older = Ping::new(1).pong().to_message();
+        let newer = Ping::new(2).pong().to_message();
+        let ping = message(MessageType::Ping);
+        let disconnect = message(MessageType::Disconnect);
+        for message in [&older, &newer, &ping, &disconnect] {
+            assert_eq!(push(&queue, message).now_or_never(), Some(Ok(())));
+        }
+        assert_eq!(queue.len(), 4);
+
+        let encoder = MessageEncoder::new();
+        for message in [&newer, &ping, &disconnect, &data] {
+            assert_eq!(
+                queue.pop().await.unwrap().to_bytes(),
+                encoder.encode(message).to_bytes()
+            );
+        }
+        assert!(queue.is_empty());
+    }
+
+    #[async_std::test]
+    async fn drop_oldest_keeps_replies() {
+        let queue = OutboundQueue::new(2, OverflowPolicy::DropOldest);
+        let reply = MessageBuilder::new()
+            .with_type(MessageType::Okay)
+            .with_request_id(7)
+            .build();
+        let older = MessageBuilder::new()
+            .with_type(MessageType::extension(0x80).unwrap())
+            .with_field(b"older".to_vec())
+            .build();
+        let newer = MessageBuilder::new()
+            .with_type(MessageType::extension(0x80).unwrap())
+            .with_field(b"newer".to_vec())
+            .build();
+
+        push(&queue, &reply).await.unwrap();
+        push(&queue, &older).await.unwrap();
+        push(&queue, &newer).await.unwrap();
+        assert_eq!(queue.metrics().dropped, 1);
+
+        let encoder = MessageEncoder::new();
+        assert_eq!(
+            queue.pop().await.unwrap().to_bytes(),
+            encoder.encode(&reply).to_bytes()
+        );
+        assert_eq!(
+            queue.pop().await.unwrap().to_bytes(),
+            encoder.encode(&newer).to_bytes()
+        );
+
+        // nothing may be dropped once only replies are queued
+        push(&queue, &reply).await.unwrap();
+        push(&queue, &reply).await.unwrap();
+        assert_eq!(push(&queue, &reply).await, Err(QueueError::Overflow));
+    }
+
+    #[async_std::test]
+    async fn blocked_push_waits_for_room() {
+        let queue = OutboundQueue::new(1, OverflowPolicy::Block);
+        let check = message(MessageType::Check);
+        push(&queue, &check).await.unwrap();
+
+        let mut blocked = pin!(push(&queue, &check));
+        assert!(futures::poll!(&mut blocked).is_pending());
+        assert!(queue.pop().await.is_some());
+        assert_eq!(blocked.await, Ok(()));
+        assert_eq!(queue.metrics().blocked, 1);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::errors::{ErrorCode, MessageError};
+use crate::handshake::Capabilities;
+use crate::message::{Message, MessageBuilder, MessageType};
+use bytes::Bytes;
+use edoras_derive::Payload;
+use std::fmt::Display;
+
+/// typed content of a message, the field count and field types are checked in
+/// [`Payload::from_fields`]
+///
+/// usually implemented with `#[derive(Payload)]`
+pub trait Payload: Sized {
+    const MESSAGE_TYPE: MessageType;
+
+    /// fields that own a [`Bytes`] buffer share it instead of copying it
+    fn to_fields(&self) -> Vec<Bytes>;
+
+    fn from_fields(fields: &[&[u8]]) -> Result<Self, MessageError>;
+
+    /// builder with the type and fields of the payload, e.g. to add a request id
+    fn to_builder(&self) -> MessageBuilder {
+        MessageBuilder::new()
+            .with_type(Self::MESSAGE_TYPE)
+            .with_fields(self.to_fields())
+    }
+
+    fn to_message(&self) -> Message {
+        self.to_builder().build()
+    }
+
+    fn from_message(message: &Message) -> Result<Self, MessageError> {
+        if message.mtype() != Self::MESSAGE_TYPE {
+            return Err(MessageError::UnexpectedMessage(message.mtype()));
+        }
+
+        Self::from_fields(&message.data_ref())
+    }
+}
+
+/// a value that is sent as a single message field
+pub trait PayloadField: Sized {
+    fn to_field(&self) -> Bytes;
+
+    fn from_field(data: &[u8]) -> Option<Self>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Payload)]
+#[payload(Register)]
+pub struct RegisterRequest {
+    pub username: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Payload)]
+#[payload(Login)]
+pub struct LoginRequest {
+    pub username: String,
+}
+
+/// reply to a failed request
+#[derive(Debug, Clone, PartialEq, Eq, Payload)]
+#[payload(Error)]
+pub struct ErrorReply {
+    pub code: ErrorCode,
+    pub detail: String,
+}
+
+// IMPLEMENTATION
+
+impl ErrorReply {
+    pub fn new(code: ErrorCode, detail: impl Into<String>) -> Self {
+        Self {
+            code,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl From<&MessageError> for ErrorReply {
+    fn from(err: &MessageError) -> Self {
+        Self::new(ErrorCode::from(err), err.to_string())
+    }
+}
+
+impl Display for ErrorReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.detail)
+    }
+}
+
+impl std::error::Error for ErrorReply {}
+
+impl PayloadField for String {
+    fn to_field(&self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+
+    fn from_field(data: &[u8]) -> Option<Self> {
+        std::str::from_utf8(data).ok().map(str::to_string)
+    }
+}
+
+impl PayloadField for Vec<u8> {
+    fn to_field(&self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+
+    fn from_field(data: &[u8]) -> Option<Self> {
+        Some(data.to_vec())
+    }
+}
+
+impl PayloadField for Bytes {
+    fn to_field(&self) -> Bytes {
+        self.clone()
+    }
+
+    fn from_field(data: &[u8]) -> Option<Self> {
+        Some(Bytes::copy_from_slice(data))
+    }
+}
+
+impl PayloadField for bool {
+    fn to_field(&self) -> Bytes {
+        Bytes::copy_from_slice(&[*self as u8])
+    }
+
+    fn from_field(data: &[u8]) -> Option<Self> {
+        match data {
+            [0] => Some(false),
+            [1] => Some(true),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_payload_field_int {
+    ($($int:ty),*) => {
+        $(
+            impl PayloadField for $int {
+                fn to_field(&self) -> Bytes {
+                    Bytes::copy_from_slice(&self.to_le_bytes())
+                }
+
+                fn from_field(data: &[u8]) -> Option<Self> {
+                    data.try_into().ok().map(<$int>::from_le_bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_payload_field_int!(u8, u16, u32, u64, i64);
+
+impl PayloadField for Capabilities {
+    fn to_field(&self) -> Bytes {
+        self.bits().to_field()
+    }
+
+    fn from_field(data: &[u8]) -> Option<Self> {
+        u32::from_field(data).map(Capabilities::from_bits)
+    }
+}
+
+impl PayloadField for ErrorCode {
+    fn to_field(&self) -> Bytes {
+        self.to_code().to_field()
+    }
+
+    fn from_field(data: &[u8]) -> Option<Self> {
+        u16::from_field(data).map(ErrorCode::from_code)
+    }
+}
+
+/// reads a single field for a derived [`Payload`], not meant to be used directly
+#[doc(hidden)]
+pub fn read_field<T: PayloadField>(mtype: MessageType, data: &[u8]) -> Result<T, MessageError> {
+    T::from_field(data).ok_or(MessageError::MalformedMessage(mtype))
+}
@@ -0,0 +1,147 @@
+use crate::channel::{ChannelId, CONTROL_CHANNEL};
+use crate::chunk::StreamId;
+use crate::message::{Message, MessageBuilder, MessageField, MessageType, RequestId};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// serialized form of a [`Message`]
+///
+/// ```json
+/// { "type": "Register", "request_id": 0, "fields": [{ "utf8": "luffy" }] }
+/// ```
+///
+/// a streamed field is written as `{ "stream": <stream id> }`
+#[derive(Serialize, Deserialize)]
+struct MessageRepr {
+    #[serde(rename = "type")]
+    mtype: MessageType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_id: Option<RequestId>,
+    #[serde(default, skip_serializing_if = "is_control_channel")]
+    channel: ChannelId,
+    #[serde(default)]
+    fields: Vec<MessageField>,
+}
+
+/// readable text is kept as is, everything else is base64 encoded
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FieldRepr {
+    Utf8(String),
+    Base64(String),
+    Stream(StreamId),
+}
+
+// IMPLEMENTATION
+
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MessageRepr {
+            mtype: self.mtype(),
+            request_id: self.request_id(),
+            channel: self.channel(),
+            fields: self.fields().to_vec(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = MessageRepr::deserialize(deserializer)?;
+
+        let mut builder = MessageBuilder::new()
+            .with_type(repr.mtype)
+            .with_channel(repr.channel)
+            .with_message_fields(repr.fields);
+        if let Some(request_id) = repr.request_id {
+            builder = builder.with_request_id(request_id);
+        }
+
+        Ok(builder.build())
+    }
+}
+
+impl Serialize for MessageField {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let Some(stream_id) = self.stream_id() {
+            return FieldRepr::Stream(stream_id).serialize(serializer);
+        }
+
+        let repr = match std::str::from_utf8(&self.data) {
+            Ok(text) if is_readable(text) => FieldRepr::Utf8(text.to_string()),
+            _ => FieldRepr::Base64(BASE64.encode(&self.data)),
+        };
+
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageField {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = match FieldRepr::deserialize(deserializer)? {
+            FieldRepr::Utf8(text) => text.into_bytes(),
+            FieldRepr::Base64(data) => BASE64.decode(data).map_err(D::Error::custom)?,
+            FieldRepr::Stream(stream_id) => return Ok(MessageField::streamed(stream_id)),
+        };
+
+        Ok(MessageField::new(data.into()))
+    }
+}
+
+/// integers and other binary data are often valid UTF-8, but full of control characters
+fn is_readable(text: &str) -> bool {
+    text.chars()
+        .all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+}
+
+fn is_control_channel(channel: &ChannelId) -> bool {
+    *channel == CONTROL_CHANNEL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn message_round_trips_through_json() {
+        let message = MessageBuilder::new()
+            .with_type(MessageType::extension(0x80).unwrap())
+            .with_request_id(7)
+            .with_field(b"luffy".to_vec())
+            .with_field(vec![0, 1, 0xff])
+            .with_streamed_field(3)
+            .build();
+
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "type": { "Extension": 0x80 },
+                "request_id": 7,
+                "fields": [{ "utf8": "luffy" }, { "base64": "AAH/" }, { "stream": 3 }],
+            })
+        );
+
+        let decoded: Message = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded.mtype(), message.mtype());
+        assert_eq!(decoded.request_id(), Some(7));
+        assert_eq!(decoded.stream_ids(), [3]);
+        assert_eq!(
+            decoded.data()[..2],
+            [
+                Bytes::from_static(b"luffy"),
+                Bytes::from_static(&[0, 1, 0xff])
+            ]
+        );
+    }
+
+    #[test]
+    fn extension_outside_of_range_is_rejected() {
+        let json = r#"{ "type": { "Extension": 5 }, "fields": [] }"#;
+        assert!(serde_json::from_str::<Message>(json).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use futures_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use futures_rustls::rustls::crypto::{self, CryptoProvider};
+use futures_rustls::rustls::pki_types::pem::PemObject;
+use futures_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use futures_rustls::rustls::{
+    ClientConfig, DigitallySignedStruct, Error as TlsError, RootCertStore, ServerConfig,
+    SignatureScheme,
+};
+pub use futures_rustls::{client, server, TlsAcceptor, TlsConnector};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// SHA-256 hash of a DER encoded certificate
+pub type Fingerprint = [u8; 32];
+
+/// PEM file of the CA the certificate of the server is signed by
+pub const TLS_CA_VAR: &str = "EDORAS_TLS_CA";
+/// SHA-256 fingerprint of the certificate of the server in hex, replaces the CA
+pub const TLS_FINGERPRINT_VAR: &str = "EDORAS_TLS_FINGERPRINT";
+
+/// how the client decides whether it trusts the certificate of the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TlsTrust {
+    /// certificates signed by the CA in the PEM file
+    Ca(PathBuf),
+    /// exactly the certificate with this fingerprint, e.g. a self-signed one
+    Fingerprint(Fingerprint),
+}
+
+/// accepts only the pinned certificate, its signatures are still checked
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    fingerprint: Fingerprint,
+    provider: Arc<CryptoProvider>,
+}
+
+// IMPLEMENTATION
+
+impl TlsTrust {
+    /// trust from [`TLS_FINGERPRINT_VAR`] or else [`TLS_CA_VAR`]
+    pub fn from_env() -> io::Result<Self> {
+        if let Ok(fingerprint) = std::env::var(TLS_FINGERPRINT_VAR) {
+            return match parse_fingerprint(&fingerprint) {
+                Some(fingerprint) => Ok(Self::Fingerprint(fingerprint)),
+                None => Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid TLS fingerprint: {}", fingerprint),
+                )),
+            };
+        }
+
+        match std::env::var_os(TLS_CA_VAR) {
+            Some(path) => Ok(Self::Ca(path.into())),
+            None => Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("TLS needs {} or {}", TLS_CA_VAR, TLS_FINGERPRINT_VAR),
+            )),
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, TlsError> {
+        if fingerprint(end_entity) != self.fingerprint {
+            return Err(TlsError::General(String::from(
+                "Certificate does not match the pinned fingerprint",
+            )));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// acceptor for a server with the certificate chain and private key from the PEM files
+pub fn acceptor(
+    cert_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+) -> io::Result<TlsAcceptor> {
+    let certs = load_certs(cert_path.as_ref())?;
+    let key = PrivateKeyDer::from_pem_file(key_path.as_ref()).map_err(invalid_data)?;
+
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(invalid_data)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// connector for a client that trusts the server as configured
+pub fn connector(trust: &TlsTrust) -> io::Result<TlsConnector> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_data)?;
+
+    let config = match trust {
+        TlsTrust::Ca(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(path)? {
+                roots.add(cert).map_err(invalid_data)?;
+            }
+
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+        TlsTrust::Fingerprint(fingerprint) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+                fingerprint: *fingerprint,
+                provider: provider(),
+            }))
+            .with_no_client_auth(),
+    };
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// name the certificate of the server has to be valid for, accepts host names and ip addresses
+pub fn server_name(host: &str) -> io::Result<ServerName<'static>> {
+    ServerName::try_from(host.to_string()).map_err(invalid_data)
+}
+
+pub fn fingerprint(cert: &CertificateDer<'_>) -> Fingerprint {
+    let digest = ring::digest::digest(&ring::digest::SHA256, cert.as_ref());
+    // a SHA-256 digest always has 32 bytes
+    digest.as_ref().try_into().unwrap()
+}
+
+/// parses a fingerprint in hex, the bytes may be separated by colons
+pub fn parse_fingerprint(text: &str) -> Option<Fingerprint> {
+    let hex: Vec<u8> = text.bytes().filter(|&c| c != b':').collect();
+    if hex.len() != 2 * size_of::<Fingerprint>() {
+        return None;
+    }
+
+    let mut fingerprint = [0; size_of::<Fingerprint>()];
+    for (byte, pair) in fingerprint.iter_mut().zip(hex.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+
+    Some(fingerprint)
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_file_iter(path)
+        .map_err(invalid_data)?
+        .collect::<Result<_, _>>()
+        .map_err(invalid_data)
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+fn invalid_data(err: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, err)
+}
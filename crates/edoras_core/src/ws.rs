@@ -0,0 +1,151 @@
+use crate::codec::CHECKSUM_SIZE;
+use crate::limits::DecodeLimits;
+use async_tungstenite::tungstenite::protocol::WebSocketConfig;
+use async_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use async_tungstenite::WebSocketStream;
+use bytes::{Buf, Bytes};
+use futures::{ready, AsyncRead, AsyncWrite, Sink, Stream};
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// byte stream over a WebSocket connection
+///
+/// every flush sends the bytes written so far as one binary message, so each frame written with
+/// [`Frame::send`](crate::Frame::send) ends up in a message of its own. Received binary messages
+/// are read in order, text messages are rejected
+#[derive(Debug)]
+pub struct WsStream<S> {
+    inner: WebSocketStream<S>,
+    read_buf: Bytes,    // rest of the last received message
+    write_buf: Vec<u8>, // message that is sent on the next flush
+}
+
+// IMPLEMENTATION
+
+impl<S: AsyncRead + AsyncWrite + Unpin> WsStream<S> {
+    /// runs the server side of the WebSocket handshake, messages larger than the biggest frame
+    /// the limits allow are rejected before they are buffered
+    pub async fn accept(stream: S, limits: DecodeLimits) -> io::Result<Self> {
+        let max_size = limits.max_frame_size() + CHECKSUM_SIZE;
+        let config = WebSocketConfig::default()
+            .max_message_size(Some(max_size))
+            .max_frame_size(Some(max_size));
+
+        let inner = async_tungstenite::accept_async_with_config(stream, Some(config))
+            .await
+            .map_err(into_io_error)?;
+        Ok(Self::new(inner))
+    }
+
+    /// runs the client side of the WebSocket handshake, the url is only used for the request
+    pub async fn connect(url: &str, stream: S) -> io::Result<Self> {
+        let (inner, _) = async_tungstenite::client_async(url, stream)
+            .await
+            .map_err(into_io_error)?;
+        Ok(Self::new(inner))
+    }
+
+    fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            read_buf: Bytes::new(),
+            write_buf: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.inner.get_ref()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        while this.read_buf.is_empty() {
+            match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(WsMessage::Binary(data))) => this.read_buf = data,
+                Some(Ok(WsMessage::Text(_))) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Text messages are not supported",
+                    )))
+                }
+                // pings are answered by the WebSocket itself
+                Some(Ok(WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_))) => {}
+                Some(Ok(WsMessage::Close(_))) | None => return Poll::Ready(Ok(0)),
+                Some(Err(e)) => return Poll::Ready(Err(into_io_error(e))),
+            }
+        }
+
+        let size = buf.len().min(this.read_buf.len());
+        buf[..size].copy_from_slice(&this.read_buf[..size]);
+        this.read_buf.advance(size);
+
+        Poll::Ready(Ok(size))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().write_buf.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut size = 0;
+        for buf in bufs {
+            this.write_buf.extend_from_slice(buf);
+            size += buf.len();
+        }
+
+        Poll::Ready(Ok(size))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let mut inner = Pin::new(&mut this.inner);
+
+        if !this.write_buf.is_empty() {
+            ready!(inner.as_mut().poll_ready(cx)).map_err(into_io_error)?;
+            let data = std::mem::take(&mut this.write_buf);
+            inner
+                .as_mut()
+                .start_send(WsMessage::Binary(data.into()))
+                .map_err(into_io_error)?;
+        }
+
+        inner.poll_flush(cx).map_err(into_io_error)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().inner)
+            .poll_close(cx)
+            .map_err(into_io_error)
+    }
+}
+
+fn into_io_error(err: WsError) -> io::Error {
+    match err {
+        WsError::Io(e) => e,
+        WsError::ConnectionClosed | WsError::AlreadyClosed => {
+            io::Error::from(io::ErrorKind::ConnectionAborted)
+        }
+        e => io::Error::other(e),
+    }
+}
@@ -0,0 +1,99 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident};
+
+/// derives `edoras_core::Payload` for a struct
+///
+/// every struct field becomes one message field in declaration order, so each field type has to
+/// implement `edoras_core::PayloadField`. the message type is set with `#[payload(<MessageType>)]`
+///
+/// ```ignore
+/// #[derive(Payload)]
+/// #[payload(Register)]
+/// pub struct RegisterRequest {
+///     pub username: String,
+/// }
+/// ```
+#[proc_macro_derive(Payload, attributes(payload))]
+pub fn derive_payload(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let mtype = message_type(&input)?;
+
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input,
+            "Payload can only be derived for structs",
+        ));
+    };
+
+    let count = data.fields.len();
+    let indices = 0..count;
+
+    let (to_fields, construct) = match &data.fields {
+        Fields::Named(fields) => {
+            let idents: Vec<&Ident> = fields
+                .named
+                .iter()
+                .map(|field| field.ident.as_ref().unwrap())
+                .collect();
+
+            (
+                quote! { vec![#(::edoras_core::PayloadField::to_field(&self.#idents)),*] },
+                quote! { Self { #(#idents: ::edoras_core::read_field(Self::MESSAGE_TYPE, fields[#indices])?),* } },
+            )
+        }
+        Fields::Unnamed(fields) => {
+            let members = (0..fields.unnamed.len()).map(syn::Index::from);
+
+            (
+                quote! { vec![#(::edoras_core::PayloadField::to_field(&self.#members)),*] },
+                quote! { Self(#(::edoras_core::read_field(Self::MESSAGE_TYPE, fields[#indices])?),*) },
+            )
+        }
+        Fields::Unit => (quote! { vec![] }, quote! { Self }),
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::edoras_core::Payload for #name #ty_generics #where_clause {
+            const MESSAGE_TYPE: ::edoras_core::MessageType = ::edoras_core::MessageType::#mtype;
+
+            fn to_fields(&self) -> ::std::vec::Vec<::edoras_core::Bytes> {
+                #to_fields
+            }
+
+            fn from_fields(fields: &[&[u8]]) -> ::std::result::Result<Self, ::edoras_core::MessageError> {
+                if fields.len() != #count {
+                    return Err(::edoras_core::MessageError::MalformedMessage(Self::MESSAGE_TYPE));
+                }
+
+                Ok(#construct)
+            }
+        }
+    })
+}
+
+/// reads the message type from the `#[payload(...)]` attribute
+fn message_type(input: &DeriveInput) -> syn::Result<Ident> {
+    for attr in &input.attrs {
+        if attr.path().is_ident("payload") {
+            return attr.parse_args::<Ident>();
+        }
+    }
+
+    Err(Error::new_spanned(
+        &input.ident,
+        "missing message type, add #[payload(<MessageType>)]",
+    ))
+}
@@ -0,0 +1,121 @@
+use crate::decode::{format_message, negotiated_compression};
+use crate::input::parse_message;
+use anyhow::Result as AnyResult;
+use async_std::io::prelude::BufReadExt;
+use async_std::io::BufReader;
+use async_std::net::TcpStream;
+#[cfg(unix)]
+use async_std::os::unix::net::UnixStream;
+use async_std::{future, task};
+use edoras_core::tls::{self, TlsTrust};
+use edoras_core::{
+    Capabilities, Endpoint, Hello, MessageDecoder, MessageEncoder, MessageError, Payload, WsStream,
+};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, StreamExt};
+use std::time::Duration;
+
+const SOFTWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// how long replies are printed after the last message was sent
+pub(crate) const REPLY_WAIT: Duration = Duration::from_secs(1);
+
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+/// sends the messages from stdin and prints the replies as they arrive
+///
+/// the inspector offers no capabilities in its Hello, so the frames stay as plain as possible
+pub(crate) async fn connect(endpoint: &Endpoint, raw: bool) -> AnyResult<()> {
+    let (reader, mut writer) = open(endpoint).await?.split();
+    let encoder = MessageEncoder::new();
+
+    if !raw {
+        let hello = Hello::new(SOFTWARE_VERSION, Capabilities::empty()).to_message();
+        println!("> {}", format_message(&hello));
+        encoder.send(&hello, &mut writer).await?;
+    }
+
+    let receiver = task::spawn(print_replies(reader));
+
+    let mut lines = BufReader::new(async_std::io::stdin()).lines();
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        match parse_message(line) {
+            Ok(message) => {
+                println!("> {}", format_message(&message));
+                encoder.send(&message, &mut writer).await?;
+            }
+            Err(e) => eprintln!("error: {:#}", e),
+        }
+    }
+
+    // replies to the last messages may still be on their way
+    let _ = future::timeout(REPLY_WAIT, receiver).await;
+
+    Ok(())
+}
+
+async fn print_replies(mut reader: impl AsyncRead + Unpin) {
+    let mut decoder = MessageDecoder::new();
+    while print_reply(&mut decoder, &mut reader).await {}
+}
+
+/// receives and prints the next message, false once the connection is gone
+pub(crate) async fn print_reply(
+    decoder: &mut MessageDecoder,
+    reader: &mut (impl AsyncRead + Unpin),
+) -> bool {
+    match decoder.recv(reader).await {
+        Ok(message) => {
+            println!("< {}", format_message(&message));
+            if let Some(compression) = negotiated_compression(&message) {
+                decoder.set_compression(compression);
+            }
+        }
+        Err(MessageError::ConnectionClosed) => {
+            println!("connection closed");
+            return false;
+        }
+        Err(e @ MessageError::ReadError(_)) => {
+            println!("error: {}", e);
+            return false;
+        }
+        Err(e) => println!("error: {}", e),
+    }
+
+    true
+}
+
+pub(crate) async fn open(endpoint: &Endpoint) -> AnyResult<Box<dyn Stream>> {
+    let address = match endpoint {
+        Endpoint::Tcp(address) | Endpoint::WebSocket(address) => address,
+        #[cfg(unix)]
+        Endpoint::Unix(path) => return Ok(Box::new(UnixStream::connect(path).await?)),
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => anyhow::bail!("{} needs unix socket support", endpoint),
+    };
+
+    let mut stream: Box<dyn Stream> =
+        Box::new(TcpStream::connect((address.host.as_str(), address.port)).await?);
+
+    if address.tls {
+        let connector = tls::connector(&TlsTrust::from_env()?)?;
+        stream = Box::new(
+            connector
+                .connect(tls::server_name(&address.host)?, stream)
+                .await?,
+        );
+    }
+
+    if endpoint.is_websocket() {
+        stream = Box::new(WsStream::connect(&format!("{}/", endpoint), stream).await?);
+    }
+
+    Ok(stream)
+}
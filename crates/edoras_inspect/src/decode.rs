@@ -0,0 +1,189 @@
+use anyhow::{bail, Context, Result as AnyResult};
+use edoras_core::{
+    CaptureReader, CaptureSide, Compression, Direction, Message, MessageDecoder, MessageType,
+    Payload, Welcome, CAPTURE_MAGIC,
+};
+use std::io::Read;
+
+/// decodes the hex or byte dumps as one stream of bytes
+pub(crate) fn decode_dumps(dumps: &[String], compression: Compression) -> AnyResult<()> {
+    let mut data = Vec::new();
+    for dump in dumps {
+        data.extend(parse_dump(dump)?);
+    }
+
+    decode(&data, compression);
+    Ok(())
+}
+
+/// decodes the captured bytes or the capture in the file, `-` reads stdin
+pub(crate) fn decode_file(path: &str, compression: Compression) -> AnyResult<()> {
+    let data = match path {
+        "-" => {
+            let mut data = Vec::new();
+            std::io::stdin().read_to_end(&mut data)?;
+            data
+        }
+        path => std::fs::read(path).with_context(|| format!("Failed to read {}", path))?,
+    };
+
+    if data.starts_with(&CAPTURE_MAGIC) {
+        return decode_capture(CaptureReader::new(data.as_slice())?);
+    }
+
+    decode(&data, compression);
+    Ok(())
+}
+
+/// prints every record of the capture with its time since the first one, the compression follows
+/// the Welcome of the capture
+fn decode_capture(capture: CaptureReader<&[u8]>) -> AnyResult<()> {
+    match capture.side() {
+        CaptureSide::Client => println!("capture of a client"),
+        CaptureSide::Server => println!("capture of a server session"),
+    }
+
+    let mut compression = Compression::None;
+    let mut start = None;
+
+    for record in capture {
+        let record = record?;
+        let start = *start.get_or_insert(record.timestamp);
+        let time = record.timestamp.saturating_sub(start).as_secs_f64();
+        let direction = match record.direction {
+            Direction::Incoming => '<',
+            Direction::Outgoing => '>',
+        };
+
+        match decode_frame(&record.frame, &mut compression) {
+            Ok(message) => println!("{:10.3} {} {}", time, direction, format_message(&message)),
+            Err(e) => println!("{:10.3} {} error: {:#}", time, direction, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// decodes a single captured frame, a Welcome changes the compression of the following frames
+pub(crate) fn decode_frame(frame: &[u8], compression: &mut Compression) -> AnyResult<Message> {
+    let mut decoder = MessageDecoder::new();
+    decoder.set_compression(*compression);
+    decoder.feed(frame);
+
+    let Some(message) = decoder.decode()? else {
+        bail!("incomplete frame");
+    };
+    if let Some(negotiated) = negotiated_compression(&message) {
+        *compression = negotiated;
+    }
+    Ok(message)
+}
+
+/// compression of the frames after the message if it is a Welcome
+pub(crate) fn negotiated_compression(message: &Message) -> Option<Compression> {
+    if message.mtype() != MessageType::Welcome {
+        return None;
+    }
+
+    let welcome = Welcome::from_message(message).ok()?;
+    Some(Compression::from_capabilities(welcome.capabilities()))
+}
+
+/// prints every message in the data, broken frames are reported and skipped
+fn decode(data: &[u8], compression: Compression) {
+    let mut decoder = MessageDecoder::new();
+    decoder.set_compression(compression);
+    decoder.feed(data);
+
+    loop {
+        match decoder.decode() {
+            Ok(Some(message)) => println!("{}", format_message(&message)),
+            Ok(None) => break,
+            Err(e) => println!("error: {}", e),
+        }
+    }
+
+    if decoder.buffered() > 0 {
+        println!("{} bytes left without a complete frame", decoder.buffered());
+    }
+}
+
+/// the message as a line of JSON
+pub(crate) fn format_message(message: &Message) -> String {
+    serde_json::to_string(message).unwrap_or_else(|e| format!("error: {}", e))
+}
+
+/// parses hex like `01 3c 21 3e` or byte strings like `b"\x01<!>"` as printed in the logs, the
+/// brackets and commas of a list of byte strings are skipped
+fn parse_dump(dump: &str) -> AnyResult<Vec<u8>> {
+    if !dump.contains("b\"") {
+        return parse_hex(dump);
+    }
+
+    let mut data = Vec::new();
+    let mut rest = dump;
+    while let Some(start) = rest.find("b\"") {
+        let (bytes, len) = parse_byte_string(&rest[start + 2..])?;
+        data.extend(bytes);
+        rest = &rest[start + 2 + len..];
+    }
+
+    Ok(data)
+}
+
+/// hex digits, whitespace, colons, commas and `0x` prefixes are allowed between the bytes
+pub(crate) fn parse_hex(dump: &str) -> AnyResult<Vec<u8>> {
+    let digits: Vec<u8> = dump
+        .split_whitespace()
+        .map(|word| word.trim_start_matches("0x"))
+        .flat_map(|word| word.bytes())
+        .filter(|&c| c != b':' && c != b',')
+        .collect();
+
+    if !digits.len().is_multiple_of(2) {
+        bail!("odd number of hex digits");
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair)?;
+            u8::from_str_radix(pair, 16).with_context(|| format!("invalid hex {}", pair))
+        })
+        .collect()
+}
+
+/// parses a byte string up to its closing quote, returns the bytes and the length of the literal
+fn parse_byte_string(literal: &str) -> AnyResult<(Vec<u8>, usize)> {
+    let mut bytes = Vec::new();
+    let mut chars = literal.char_indices();
+
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '"' => return Ok((bytes, pos + 1)),
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some((_, 'x')) => {
+                        let hex: String = chars.by_ref().take(2).map(|(_, c)| c).collect();
+                        u8::from_str_radix(&hex, 16)
+                            .with_context(|| format!("invalid escape \\x{}", hex))?
+                    }
+                    Some((_, 'n')) => b'\n',
+                    Some((_, 'r')) => b'\r',
+                    Some((_, 't')) => b'\t',
+                    Some((_, '0')) => b'\0',
+                    Some((_, c @ ('\\' | '"' | '\''))) => c as u8,
+                    Some((_, c)) => bail!("invalid escape \\{}", c),
+                    None => break,
+                };
+                bytes.push(escaped);
+            }
+            c => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+
+    bail!("unterminated byte string")
+}
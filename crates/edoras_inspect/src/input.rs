@@ -0,0 +1,62 @@
+use crate::decode::parse_hex;
+use anyhow::{bail, Context, Result as AnyResult};
+use edoras_core::{Message, MessageBuilder, MessageType, MessageTypeCode};
+
+/// parses a hand-built message, either as JSON or as `<TYPE> [#REQUEST_ID] [@CHANNEL] [FIELD]...`
+pub(crate) fn parse_message(line: &str) -> AnyResult<Message> {
+    if line.starts_with('{') {
+        return serde_json::from_str(line).context("invalid message");
+    }
+
+    let mut words = line.split_whitespace();
+    let Some(mtype) = words.next() else {
+        bail!("empty message");
+    };
+
+    let mut builder = MessageBuilder::new().with_type(parse_type(mtype)?);
+    for word in words {
+        if let Some(request_id) = word.strip_prefix('#') {
+            builder = builder.with_request_id(request_id.parse().context("invalid request id")?);
+        } else if let Some(channel) = word.strip_prefix('@') {
+            builder = builder.with_channel(channel.parse().context("invalid channel")?);
+        } else {
+            builder = builder.with_field(parse_field(word)?);
+        }
+    }
+
+    Ok(builder.build())
+}
+
+/// the name of a message type like `Register` or its code like `0x2b`
+fn parse_type(name: &str) -> AnyResult<MessageType> {
+    if let Some(code) = name.strip_prefix("0x") {
+        let code = MessageTypeCode::from_str_radix(code, 16).context("invalid type code")?;
+        return Ok(MessageType::from_code(code)?);
+    }
+
+    (MessageTypeCode::MIN..=MessageTypeCode::MAX)
+        .filter_map(|code| MessageType::from_code(code).ok())
+        .find(|mtype| !mtype.is_extension() && format!("{:?}", mtype).eq_ignore_ascii_case(name))
+        .with_context(|| format!("unknown message type {}", name))
+}
+
+/// integers are encoded little endian like every integer field of the protocol
+fn parse_field(word: &str) -> AnyResult<Vec<u8>> {
+    let Some((kind, value)) = word.split_once(':') else {
+        return Ok(word.as_bytes().to_vec());
+    };
+
+    let field = match kind {
+        "str" => value.as_bytes().to_vec(),
+        "hex" => parse_hex(value).with_context(|| format!("invalid field {}", word))?,
+        "u8" => value.parse::<u8>()?.to_le_bytes().to_vec(),
+        "u16" => value.parse::<u16>()?.to_le_bytes().to_vec(),
+        "u32" => value.parse::<u32>()?.to_le_bytes().to_vec(),
+        "u64" => value.parse::<u64>()?.to_le_bytes().to_vec(),
+        "bool" => vec![value.parse::<bool>()? as u8],
+        // text that happens to contain a colon
+        _ => word.as_bytes().to_vec(),
+    };
+
+    Ok(field)
+}
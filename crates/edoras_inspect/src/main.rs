@@ -0,0 +1,105 @@
+mod connect;
+mod decode;
+mod input;
+mod replay;
+
+use anyhow::{bail, Context, Result as AnyResult};
+use edoras_core::{Compression, Endpoint};
+
+const USAGE: &str = "\
+usage:
+  inspect decode [--compression <deflate|zstd>] <HEX|DUMP>...
+  inspect decode [--compression <deflate|zstd>] --file <PATH>
+  inspect connect [--raw] <ENDPOINT>
+  inspect replay [--client] [--fast] <CAPTURE> <ENDPOINT>
+
+decode   prints the messages in hex or in byte dumps like b\"\\x01<!>...\" as they appear in the
+         logs, or in a file of captured bytes or a capture (- reads stdin)
+connect  sends the messages read from stdin, one per line, and prints the replies. A line is
+         either a message in JSON or <TYPE> [#REQUEST_ID] [@CHANNEL] [FIELD]... with fields like
+         text, str:text, hex:0a0b, u8:1, u16:1, u32:1, u64:1 or bool:true. --raw skips the
+         handshake
+replay   sends the frames the client sent in a capture to the server at the endpoint and prints
+         the replies. --client waits for a client on the endpoint and sends it the frames of the
+         server instead. --fast skips the pauses between the frames";
+
+#[async_std::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if let Err(e) = run(&args).await {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: &[String]) -> AnyResult<()> {
+    let Some((command, args)) = args.split_first() else {
+        bail!("missing command\n\n{}", USAGE);
+    };
+
+    match command.as_str() {
+        "decode" => {
+            let (compression, args) = compression_option(args)?;
+            match args {
+                [flag, path] if flag == "--file" => decode::decode_file(path, compression),
+                [] => bail!("nothing to decode\n\n{}", USAGE),
+                dumps => decode::decode_dumps(dumps, compression),
+            }
+        }
+        "connect" => {
+            let (raw, args) = match args {
+                [flag, args @ ..] if flag == "--raw" => (true, args),
+                args => (false, args),
+            };
+            let [endpoint] = args else {
+                bail!("expected one endpoint\n\n{}", USAGE);
+            };
+
+            let endpoint: Endpoint = endpoint
+                .parse()
+                .with_context(|| format!("Invalid endpoint {}", endpoint))?;
+            connect::connect(&endpoint, raw).await
+        }
+        "replay" => {
+            let mut client = false;
+            let mut fast = false;
+            let mut args = args;
+            while let [flag, rest @ ..] = args {
+                match flag.as_str() {
+                    "--client" => client = true,
+                    "--fast" => fast = true,
+                    _ => break,
+                }
+                args = rest;
+            }
+            let [capture, endpoint] = args else {
+                bail!("expected a capture and an endpoint\n\n{}", USAGE);
+            };
+
+            let endpoint: Endpoint = endpoint
+                .parse()
+                .with_context(|| format!("Invalid endpoint {}", endpoint))?;
+            replay::replay(capture, &endpoint, client, fast).await
+        }
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        command => bail!("unknown command {}\n\n{}", command, USAGE),
+    }
+}
+
+fn compression_option(args: &[String]) -> AnyResult<(Compression, &[String])> {
+    match args {
+        [flag, name, args @ ..] if flag == "--compression" => {
+            let compression = match name.as_str() {
+                "deflate" => Compression::Deflate,
+                "zstd" => Compression::Zstd,
+                name => bail!("unknown compression {}", name),
+            };
+            Ok((compression, args))
+        }
+        args => Ok((Compression::None, args)),
+    }
+}
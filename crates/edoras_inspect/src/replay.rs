@@ -0,0 +1,109 @@
+use crate::connect::{open, print_reply, Stream, REPLY_WAIT};
+use crate::decode::{decode_frame, format_message, negotiated_compression};
+use anyhow::{bail, Context, Result as AnyResult};
+use async_std::future;
+use async_std::net::TcpListener;
+#[cfg(unix)]
+use async_std::os::unix::net::UnixListener;
+use edoras_core::{CaptureReader, Compression, Endpoint, MessageDecoder};
+use futures::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use std::time::Duration;
+
+/// sends the frames of one side of the capture and prints what the other side replies
+///
+/// against a server the frames of the client are replayed, with `client` the inspector waits for
+/// a client and replays the frames of the server. The frames are sent as they were captured with
+/// their original gaps unless `fast` is set
+pub(crate) async fn replay(
+    path: &str,
+    endpoint: &Endpoint,
+    client: bool,
+    fast: bool,
+) -> AnyResult<()> {
+    let capture =
+        CaptureReader::open(path).with_context(|| format!("Failed to open capture {}", path))?;
+    let direction = match client {
+        true => capture.side().server_frames(),
+        false => capture.side().client_frames(),
+    };
+    let records = capture.collect::<Result<Vec<_>, _>>()?;
+
+    let stream = match client {
+        true => accept(endpoint).await?,
+        false => open(endpoint).await?,
+    };
+    let (mut reader, mut writer) = stream.split();
+    let mut decoder = MessageDecoder::new();
+    let mut compression = Compression::None; // of the captured frames
+    let mut last_sent = None;
+
+    for record in records {
+        // every frame is decoded to follow the compression of the capture
+        let message = decode_frame(&record.frame, &mut compression);
+        if record.direction != direction {
+            continue;
+        }
+
+        let gap = match last_sent {
+            Some(last) if !fast => record.timestamp.saturating_sub(last),
+            _ => Duration::ZERO,
+        };
+        last_sent = Some(record.timestamp);
+        if !print_replies(&mut decoder, &mut reader, gap).await {
+            return Ok(());
+        }
+
+        match message {
+            Ok(message) => {
+                println!("> {}", format_message(&message));
+                if let Some(compression) = negotiated_compression(&message) {
+                    decoder.set_compression(compression);
+                }
+            }
+            Err(e) => println!("> error: {:#}", e),
+        }
+        writer.write_all(&record.frame).await?;
+        writer.flush().await?;
+    }
+
+    // replies to the last frames may still be on their way
+    print_replies(&mut decoder, &mut reader, REPLY_WAIT).await;
+
+    Ok(())
+}
+
+/// prints the messages that arrive within the duration, false once the connection is gone
+async fn print_replies(
+    decoder: &mut MessageDecoder,
+    reader: &mut (impl AsyncRead + Unpin),
+    duration: Duration,
+) -> bool {
+    let receive = async {
+        while print_reply(decoder, reader).await {}
+        false
+    };
+
+    future::timeout(duration, receive).await.unwrap_or(true)
+}
+
+/// waits for the client the frames of the server are replayed to
+async fn accept(endpoint: &Endpoint) -> AnyResult<Box<dyn Stream>> {
+    let stream: Box<dyn Stream> = match endpoint {
+        Endpoint::Tcp(address) if !address.tls => {
+            let listener = TcpListener::bind((address.host.as_str(), address.port)).await?;
+            println!("waiting for a client on {}", listener.local_addr()?);
+            Box::new(listener.accept().await?.0)
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            let listener = UnixListener::bind(path).await?;
+            println!("waiting for a client on {}", endpoint);
+            let stream = listener.accept().await?.0;
+            let _ = std::fs::remove_file(path);
+            Box::new(stream)
+        }
+        _ => bail!("replaying to a client needs an edoras:// or unix:// endpoint"),
+    };
+
+    Ok(stream)
+}
@@ -1,22 +1,59 @@
 use crate::server;
 use crate::session::Session;
 use crate::user::User;
-use anyhow::Result as AnyResult;
+use anyhow::{bail, Context, Result as AnyResult};
 use async_std::sync::RwLock;
-use edoras_core::{HOST, PORT};
+use edoras_core::{Capabilities, DecodeLimits, Endpoint, OverflowPolicy, HOST, PORT, WS_PORT};
 use std::collections::HashMap;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 use uuid::Uuid;
 
 pub(crate) const CONNECTION_LIMIT: usize = 8;
 pub(crate) const HEALTH_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// how long a client may take to acknowledge a Disconnect of the server
+pub(crate) const DISCONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+/// how long the writer of a session that ends may take to write the frames that are left
+pub(crate) const FLUSH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+/// a client that does not answer a Ping within this time is disconnected
+pub(crate) const PONG_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+pub(crate) const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+pub(crate) const SOFTWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub(crate) const CAPABILITIES: Capabilities = Capabilities::CHECKSUM
+    .union(Capabilities::DEFLATE)
+    .union(Capabilities::ZSTD)
+    .union(Capabilities::CHANNELS);
+
+pub(crate) const DECODE_LIMITS: DecodeLimits = DecodeLimits::new(64, 64 * 1024, 256 * 1024);
+pub(crate) const AUTH_DECODE_LIMITS: DecodeLimits = DecodeLimits::new(1, 64, 128);
+pub(crate) const HANDSHAKE_DECODE_LIMITS: DecodeLimits = DecodeLimits::new(4, 64, 512);
+pub(crate) const DISCONNECT_DECODE_LIMITS: DecodeLimits = DecodeLimits::new(2, 256, 512);
+pub(crate) const KEEPALIVE_DECODE_LIMITS: DecodeLimits = DecodeLimits::new(2, 8, 64);
+
+/// paths of the PEM encoded certificate chain and private key, TLS is enabled if both are set
+#[cfg(feature = "tls")]
+const TLS_CERT_VAR: &str = "EDORAS_TLS_CERT";
+#[cfg(feature = "tls")]
+const TLS_KEY_VAR: &str = "EDORAS_TLS_KEY";
+
+/// comma separated endpoints the server listens on if none are given on the command line
+const LISTEN_VAR: &str = "EDORAS_LISTEN";
+
+/// directory the frames of every session are captured to, capturing is off if it is not set
+const CAPTURE_DIR_VAR: &str = "EDORAS_CAPTURE_DIR";
+
+/// frames queued per session before the overflow policy applies
+const QUEUE_CAPACITY_VAR: &str = "EDORAS_QUEUE_CAPACITY";
+/// what happens when the queue of a session is full: `drop-oldest`, `disconnect` or `block`
+const QUEUE_POLICY_VAR: &str = "EDORAS_QUEUE_POLICY";
 
 const TRACING_LEVEL: tracing::Level = tracing::Level::DEBUG;
 
 #[derive(Debug)]
 pub(crate) struct AppData {
-    users: HashMap<String, User>,                  // username -> User
-    sessions: HashMap<Uuid, Arc<RwLock<Session>>>, // session_id -> Session
+    users: HashMap<String, User>,     // username -> User
+    sessions: HashMap<Uuid, Session>, // session_id -> Session
 }
 
 pub(crate) struct App {
@@ -24,6 +61,7 @@ pub(crate) struct App {
     data: Arc<RwLock<AppData>>,
 }
 
+#[allow(dead_code)]
 impl AppData {
     pub fn new() -> Self {
         Self {
@@ -48,26 +86,38 @@ impl AppData {
         self.users.remove(username)
     }
 
-    pub fn get_session(&self, session_id: &Uuid) -> Option<Arc<RwLock<Session>>> {
+    pub fn get_session(&self, session_id: &Uuid) -> Option<Session> {
         self.sessions.get(session_id).cloned()
     }
 
-    pub fn get_session_mut(&mut self, session_id: &Uuid) -> Option<&mut Arc<RwLock<Session>>> {
+    pub fn get_session_mut(&mut self, session_id: &Uuid) -> Option<&mut Session> {
         self.sessions.get_mut(session_id)
     }
 
-    pub fn insert_session(
-        &mut self,
-        session_id: Uuid,
-        session: Arc<RwLock<Session>>,
-    ) -> Option<Arc<RwLock<Session>>> {
+    pub fn insert_session(&mut self, session_id: Uuid, session: Session) -> Option<Session> {
         self.sessions.insert(session_id, session)
     }
 
-    pub fn remove_session(&mut self, session_id: &Uuid) -> Option<Arc<RwLock<Session>>> {
+    pub fn remove_session(&mut self, session_id: &Uuid) -> Option<Session> {
         self.sessions.remove(session_id)
     }
 
+    /// forgets the session and detaches it from its user, can be called more than once
+    pub fn end_session(&mut self, session: &Session) {
+        self.sessions.remove(&session.id());
+
+        let user = session
+            .user()
+            .and_then(|username| self.users.get_mut(&username));
+        if let Some(user) = user {
+            // the user may be logged in with a newer session by now
+            if user.session() == Some(session.id()) {
+                user.clear_session();
+            }
+        }
+        session.remove_user();
+    }
+
     pub fn user_exists(&self, username: &str) -> bool {
         self.users.contains_key(username)
     }
@@ -85,12 +135,76 @@ impl App {
             .init();
 
         Self {
-            server: server::Server::new(HOST, PORT),
+            server: server::Server::new(),
             data: Arc::new(RwLock::new(AppData::new())),
         }
     }
 
+    /// endpoints from the command line, the environment or the defaults
+    fn endpoints() -> AnyResult<Vec<Endpoint>> {
+        let mut addresses: Vec<String> = std::env::args().skip(1).collect();
+        if addresses.is_empty() {
+            if let Ok(listen) = std::env::var(LISTEN_VAR) {
+                addresses = listen.split(',').map(|a| a.trim().to_string()).collect();
+            }
+        }
+
+        if addresses.is_empty() {
+            let mut endpoints = vec![Endpoint::tcp(HOST, PORT)];
+            if cfg!(feature = "ws") {
+                endpoints.push(Endpoint::websocket(HOST, WS_PORT));
+            }
+            return Ok(endpoints);
+        }
+
+        addresses
+            .iter()
+            .map(|address| {
+                address
+                    .parse()
+                    .with_context(|| format!("Invalid endpoint {}", address))
+            })
+            .collect()
+    }
+
+    fn overflow_policy(name: &str) -> AnyResult<OverflowPolicy> {
+        match name {
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            "block" => Ok(OverflowPolicy::Block),
+            _ => bail!("Invalid queue policy {}", name),
+        }
+    }
+
     pub async fn run(&mut self) -> AnyResult<()> {
+        #[cfg(feature = "tls")]
+        if let (Ok(cert), Ok(key)) = (std::env::var(TLS_CERT_VAR), std::env::var(TLS_KEY_VAR)) {
+            self.server.set_tls(edoras_core::tls::acceptor(cert, key)?);
+        }
+
+        if let Some(dir) = std::env::var_os(CAPTURE_DIR_VAR) {
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create capture directory {:?}", dir))?;
+            self.server.set_capture_dir(dir.into());
+        }
+
+        if let Ok(capacity) = std::env::var(QUEUE_CAPACITY_VAR) {
+            // an empty queue could not even take a reply
+            let capacity: NonZeroUsize = capacity
+                .parse()
+                .with_context(|| format!("Invalid queue capacity {}", capacity))?;
+            self.server.set_queue_capacity(capacity.get());
+        }
+
+        if let Ok(policy) = std::env::var(QUEUE_POLICY_VAR) {
+            self.server
+                .set_overflow_policy(Self::overflow_policy(&policy)?);
+        }
+
+        for endpoint in Self::endpoints()? {
+            self.server.add_endpoint(endpoint);
+        }
+
         self.server.serve(self.data.clone()).await?;
 
         Ok(())
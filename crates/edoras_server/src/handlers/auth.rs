@@ -1,77 +1,92 @@
+use super::{reply, reply_error};
 use crate::application::AppData;
 use crate::session::Session;
 use crate::user::User;
 use async_std::sync::RwLock;
-use edoras_core::Message;
-use std::str;
+use edoras_core::{
+    ErrorCode, ErrorReply, LoginRequest, Message, MessageBuilder, MessageType, RegisterRequest,
+};
 use std::sync::Arc;
 
-// TODO: Error handling
-
 pub(crate) async fn handle_register(
-    session: Arc<RwLock<Session>>,
+    session: Session,
     appdata: Arc<RwLock<AppData>>,
     message: &Message,
+    request: RegisterRequest,
 ) {
-    if session.read().await.user().is_some() {
+    let user = session.user();
+    if let Some(user) = user {
+        let detail = format!("Already authenticated as {}", user);
+        let error = ErrorReply::new(ErrorCode::AlreadyAuthenticated, detail);
+        reply_error(&session, message, error).await;
         return;
     }
 
-    if message.field_count() != 1 {
-        return;
-    }
-
-    let username = match str::from_utf8(message.data_ref()[0]) {
-        Ok(username) => username,
-        Err(_) => return,
-    };
+    let username = request.username;
 
     // TODO: validate username with pattern [a-zA-Z0-9_]{3,20}
 
     tracing::info!("Registering user {}", username);
 
-    if appdata.read().await.get_user(username).is_some() {
+    // checked and inserted under the same lock, so a name can only be registered once
+    let mut appdata = appdata.write().await;
+    if appdata.get_user(&username).is_some() {
+        drop(appdata);
         tracing::info!("User {} already exists", username);
+        let detail = format!("Username {} is already taken", username);
+        let error = ErrorReply::new(ErrorCode::UsernameTaken, detail);
+        reply_error(&session, message, error).await;
         return;
     }
 
-    let mut user = User::new(username.to_string());
-    user.set_session(session.read().await.id());
-    session.write().await.set_user(username.to_string());
-    appdata
-        .write()
-        .await
-        .insert_user(username.to_string(), user);
+    let mut user = User::new(username.clone());
+    user.set_session(session.id());
+    session.set_user(username.clone());
+    appdata.insert_user(username, user);
+    drop(appdata);
+
+    reply(
+        &session,
+        message,
+        MessageBuilder::new().with_type(MessageType::Okay),
+    )
+    .await;
 }
 
 pub(crate) async fn handle_login(
-    session: Arc<RwLock<Session>>,
+    session: Session,
     appdata: Arc<RwLock<AppData>>,
     message: &Message,
+    request: LoginRequest,
 ) {
-    if session.read().await.user().is_some() {
+    let user = session.user();
+    if let Some(user) = user {
+        let detail = format!("Already authenticated as {}", user);
+        let error = ErrorReply::new(ErrorCode::AlreadyAuthenticated, detail);
+        reply_error(&session, message, error).await;
         return;
     }
 
-    if message.field_count() != 1 {
-        return;
-    }
-
-    let username = match str::from_utf8(message.data_ref()[0]) {
-        Ok(username) => username,
-        Err(_) => return,
-    };
+    let username = request.username;
 
-    if appdata.read().await.get_user(username).is_none() {
+    let mut appdata = appdata.write().await;
+    let Some(user) = appdata.get_user_mut(&username) else {
+        drop(appdata);
         tracing::info!("User {} not found", username);
+        let detail = format!("User {} does not exist", username);
+        let error = ErrorReply::new(ErrorCode::UserNotFound, detail);
+        reply_error(&session, message, error).await;
         return;
-    }
+    };
+
+    user.set_session(session.id());
+    session.set_user(username.clone());
+    drop(appdata);
 
-    session.write().await.set_user(username.to_string());
-    appdata
-        .write()
-        .await
-        .get_user_mut(username)
-        .unwrap()
-        .set_session(session.read().await.id());
+    reply(
+        &session,
+        message,
+        MessageBuilder::new().with_type(MessageType::Okay),
+    )
+    .await;
 }
@@ -3,42 +3,109 @@ mod auth;
 use crate::application::AppData;
 use crate::session::Session;
 use async_std::sync::RwLock;
-use edoras_core::{Message, MessageBuilder, MessageType};
+use edoras_core::{
+    Disconnect, DisconnectReason, ErrorCode, ErrorReply, LoginRequest, Message, MessageBuilder,
+    MessageError, MessageType, Payload, Pong, RegisterRequest,
+};
 use std::sync::Arc;
 
-pub async fn handle_message(
-    session: Arc<RwLock<Session>>,
-    appdata: Arc<RwLock<AppData>>,
-    message: &Message,
-) {
-    match message.mtype() {
-        MessageType::Ping => {
-            session
-                .write()
-                .await
-                .send(MessageBuilder::new().with_type(MessageType::Pong).build())
-                .await
-                .unwrap();
+/// a message from a client with its typed payload
+#[derive(Debug)]
+pub(crate) enum Request {
+    Ping,
+    Pong(Pong),
+    Disconnect(Disconnect),
+    Login(LoginRequest),
+    Register(RegisterRequest),
+    Unsupported(MessageType),
+}
+
+impl TryFrom<&Message> for Request {
+    type Error = MessageError;
+
+    fn try_from(message: &Message) -> Result<Self, Self::Error> {
+        Ok(match message.mtype() {
+            MessageType::Ping => Self::Ping,
+            MessageType::Pong => Self::Pong(Pong::from_message(message)?),
+            // older clients send their Disconnect without a reason
+            MessageType::Disconnect if message.field_count() == 0 => {
+                Self::Disconnect(Disconnect::new(DisconnectReason::Unknown, ""))
+            }
+            MessageType::Disconnect => Self::Disconnect(Disconnect::from_message(message)?),
+            MessageType::Login => Self::Login(LoginRequest::from_message(message)?),
+            MessageType::Register => Self::Register(RegisterRequest::from_message(message)?),
+            mtype => Self::Unsupported(mtype),
+        })
+    }
+}
+
+pub async fn handle_message(session: Session, appdata: Arc<RwLock<AppData>>, message: &Message) {
+    let request = match Request::try_from(message) {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::warn!("Rejecting invalid message: {}", e);
+            reply_error(&session, message, ErrorReply::from(&e)).await;
+            return;
+        }
+    };
+
+    match request {
+        Request::Ping => {
+            // the fields are echoed as they are, so pings without nonce are answered as well
+            reply(
+                &session,
+                message,
+                MessageBuilder::new()
+                    .with_type(MessageType::Pong)
+                    .with_fields(message.data()),
+            )
+            .await;
         }
-        MessageType::Disconnect => {
-            appdata
-                .write()
-                .await
-                .remove_session(&session.read().await.id());
-            appdata
-                .write()
-                .await
-                .get_user_mut(session.read().await.user().as_ref().unwrap())
-                .unwrap()
-                .clear_session();
-            session.write().await.close();
+        Request::Disconnect(disconnect) => {
+            tracing::info!("Session {} disconnected | {}", session.id(), disconnect);
+
+            // the echo acknowledges the Disconnect, the connection is shut down afterwards
+            if let Err(e) = session.send(disconnect.to_message()).await {
+                tracing::debug!("Failed to acknowledge Disconnect | {}", e);
+            }
+            session.close();
         }
-        MessageType::Login => {
-            auth::handle_login(session, appdata, message).await;
+        Request::Login(request) => {
+            auth::handle_login(session, appdata, message, request).await;
         }
-        MessageType::Register => {
-            auth::handle_register(session, appdata, message).await;
+        Request::Register(request) => {
+            auth::handle_register(session, appdata, message, request).await;
         }
-        _ => {}
+        Request::Pong(pong) => match session.pong(&pong) {
+            Some(rtt) => tracing::debug!("Round trip to session {} | {:?}", session.id(), rtt),
+            None => tracing::debug!("Ignoring Pong of unknown Ping {}", pong.nonce),
+        },
+        Request::Unsupported(MessageType::Extension(code)) => {
+            tracing::debug!("Rejecting unsupported extension message {:#x}", code);
+            let detail = format!("Extension {:#x} is not supported", code);
+            reply_error(
+                &session,
+                message,
+                ErrorReply::new(ErrorCode::UnsupportedMessage, detail),
+            )
+            .await;
+        }
+        Request::Unsupported(mtype) => {
+            tracing::debug!("Ignoring unexpected {:?} message", mtype);
+        }
+    }
+}
+
+/// sends a reply that carries the request id of the message it answers
+pub(crate) async fn reply(session: &Session, message: &Message, reply: MessageBuilder) {
+    let reply = reply.with_reply_to(message).build();
+
+    if let Err(e) = session.send(reply).await {
+        tracing::error!("Failed to send reply: {}", e);
     }
 }
+
+/// answers the message with an Error frame
+pub(crate) async fn reply_error(session: &Session, message: &Message, error: ErrorReply) {
+    reply(session, message, error.to_builder()).await;
+}
@@ -2,6 +2,7 @@ mod application;
 mod handlers;
 mod server;
 mod session;
+mod transport;
 mod user;
 
 #[async_std::main]
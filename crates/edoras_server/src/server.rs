@@ -1,113 +1,474 @@
-use crate::application::{AppData, CONNECTION_LIMIT, HEALTH_CHECK_INTERVAL};
+use crate::application::{
+    AppData, AUTH_DECODE_LIMITS, CAPABILITIES, CONNECTION_LIMIT, DECODE_LIMITS,
+    DISCONNECT_DECODE_LIMITS, DISCONNECT_TIMEOUT, HANDSHAKE_DECODE_LIMITS, HANDSHAKE_TIMEOUT,
+    HEALTH_CHECK_INTERVAL, KEEPALIVE_DECODE_LIMITS, PONG_TIMEOUT, SOFTWARE_VERSION,
+};
 use crate::handlers::handle_message;
-use crate::session::Session;
-use anyhow::Result as AnyResult;
+use crate::session::{Session, SessionReader};
+use crate::transport::{BoxedConnection, Connection, PeerAddr};
+use anyhow::{bail, Result as AnyResult};
 use async_std::net::TcpListener;
+#[cfg(unix)]
+use async_std::os::unix::net::UnixListener;
 use async_std::sync::RwLock;
-use async_std::task;
-use edoras_core::Message;
-use futures::StreamExt;
+use async_std::{future, task};
+#[cfg(feature = "tls")]
+use edoras_core::tls::TlsAcceptor;
+#[cfg(feature = "ws")]
+use edoras_core::WsStream;
+use edoras_core::{
+    CaptureSide, CaptureWriter, Disconnect, DisconnectReason, Endpoint, ErrorReply, Hello,
+    MessageDecoder, MessageError, MessageType, OutboundQueue, OverflowPolicy, Payload, Welcome,
+    CAPTURE_EXTENSION, QUEUE_CAPACITY,
+};
+use futures::{FutureExt, Stream, StreamExt};
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
+#[derive(Clone)]
 pub(crate) struct Server {
-    host: &'static str,
-    port: u16,
+    endpoints: Vec<Endpoint>,
+    capture_dir: Option<PathBuf>,
+    queue_capacity: usize,
+    overflow_policy: OverflowPolicy,
+
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+}
+
+/// listener of an endpoint, the endpoint has the port the listener actually got
+enum Listener {
+    Tcp(TcpListener, Endpoint),
+    #[cfg(unix)]
+    Unix(UnixListener, Endpoint),
+}
+
+impl Listener {
+    fn endpoint(&self) -> &Endpoint {
+        match self {
+            Self::Tcp(_, endpoint) => endpoint,
+            #[cfg(unix)]
+            Self::Unix(_, endpoint) => endpoint,
+        }
+    }
 }
 
 impl Server {
-    pub fn new(host: &'static str, port: u16) -> Self {
+    pub fn new() -> Self {
         Self {
-            host,
-            port,
+            endpoints: Vec::new(),
+            capture_dir: None,
+            queue_capacity: QUEUE_CAPACITY,
+            overflow_policy: OverflowPolicy::default(),
+
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 
+    /// listens on the endpoint in addition to the ones added before
+    pub fn add_endpoint(&mut self, endpoint: Endpoint) {
+        self.endpoints.push(endpoint);
+    }
+
+    /// captures the frames of every session to `<session id>.edcap` in the directory
+    pub fn set_capture_dir(&mut self, dir: PathBuf) {
+        self.capture_dir = Some(dir);
+    }
+
+    /// frames queued per session before the overflow policy applies
+    pub fn set_queue_capacity(&mut self, capacity: usize) {
+        self.queue_capacity = capacity;
+    }
+
+    /// what happens to frames for a client whose queue is full
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
+
+    /// certificate for the TLS endpoints
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, acceptor: TlsAcceptor) {
+        self.tls = Some(acceptor);
+    }
+
     pub async fn serve(&self, appdata: Arc<RwLock<AppData>>) -> AnyResult<()> {
-        tracing::info!("Starting server on {}:{}", self.host, self.port);
-        let listener = TcpListener::bind((self.host, self.port)).await?;
+        tracing::info!("Starting server");
+
+        let mut listeners = Vec::new();
+        for endpoint in &self.endpoints {
+            listeners.push(self.bind(endpoint).await?);
+        }
+
+        // the connection tasks share the settings of the server
+        let server = Arc::new(self.clone());
+        let serving = listeners.iter().map(|listener| match listener {
+            Listener::Tcp(listener, endpoint) => server
+                .listen(listener.incoming(), endpoint, appdata.clone())
+                .boxed_local(),
+            #[cfg(unix)]
+            Listener::Unix(listener, endpoint) => server
+                .listen(listener.incoming(), endpoint, appdata.clone())
+                .boxed_local(),
+        });
+
         tracing::info!("Server started");
+        futures::future::join_all(serving).await;
+
+        Ok(())
+    }
 
-        listener
-            .incoming()
+    /// binds the endpoint and reports the address it is bound to, port 0 gets a free port
+    async fn bind(&self, endpoint: &Endpoint) -> AnyResult<Listener> {
+        if endpoint.is_tls() && !self.has_tls() {
+            bail!("{} needs a TLS certificate", endpoint);
+        }
+        if endpoint.is_websocket() && !cfg!(feature = "ws") {
+            bail!("{} needs WebSocket support", endpoint);
+        }
+
+        let listener = match endpoint {
+            Endpoint::Tcp(address) | Endpoint::WebSocket(address) => {
+                let listener = TcpListener::bind((address.host.as_str(), address.port)).await?;
+                let port = listener.local_addr()?.port();
+                Listener::Tcp(listener, endpoint.clone().with_port(port))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Listener::Unix(Self::bind_unix(path).await?, endpoint.clone()),
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => bail!("{} needs unix socket support", endpoint),
+        };
+
+        tracing::info!("Listening on {}", listener.endpoint());
+
+        Ok(listener)
+    }
+
+    /// binds the unix socket, a socket left over from an earlier run is replaced. Access is
+    /// controlled by the file permissions of the socket
+    #[cfg(unix)]
+    async fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+        use std::os::unix::fs::FileTypeExt;
+
+        if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+
+        UnixListener::bind(path).await
+    }
+
+    fn has_tls(&self) -> bool {
+        #[cfg(feature = "tls")]
+        return self.tls.is_some();
+        #[cfg(not(feature = "tls"))]
+        return false;
+    }
+
+    /// only accepts the connections, everything else runs in the task of each connection
+    async fn listen<S: Connection>(
+        self: &Arc<Self>,
+        incoming: impl Stream<Item = io::Result<S>>,
+        endpoint: &Endpoint,
+        appdata: Arc<RwLock<AppData>>,
+    ) {
+        incoming
             .for_each_concurrent(Some(CONNECTION_LIMIT), |stream| {
+                let server = Arc::clone(self);
+                let endpoint = endpoint.clone();
                 let data = Arc::clone(&appdata);
                 async move {
-                    if let Ok(stream) = stream {
-                        let session = Arc::new(RwLock::new(Session::new(stream)));
-                        task::spawn(Self::health_check(session.clone(), data.clone()));
-                        task::spawn(Self::handle_connection(session.clone(), data));
-                    }
+                    let Ok(stream) = stream else {
+                        return;
+                    };
+
+                    task::spawn(async move { server.connect(stream, &endpoint, data).await });
                 }
             })
             .await;
+    }
 
-        Ok(())
+    /// runs the handshakes of the transport and then the session, a peer that stalls the
+    /// handshakes only holds up its own task
+    async fn connect<S: Connection>(
+        &self,
+        stream: S,
+        endpoint: &Endpoint,
+        appdata: Arc<RwLock<AppData>>,
+    ) {
+        let stream = match self.accept(stream, endpoint).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::error!("Failed to accept connection: {}", e);
+                return;
+            }
+        };
+
+        let outbound = OutboundQueue::new(self.queue_capacity, self.overflow_policy);
+        let (session, reader) = Session::new(stream, Self::decoder(), outbound);
+        if let Some(dir) = &self.capture_dir {
+            Self::capture(&session, dir);
+        }
+
+        Self::handle_connection(session, reader, appdata).await;
     }
 
-    async fn handle_connection(session: Arc<RwLock<Session>>, appdata: Arc<RwLock<AppData>>) {
-        if let Err(e) = session.read().await.peer_addr() {
-            tracing::error!("Failed to get peer address: {}", e);
-            return;
+    fn capture(session: &Session, dir: &Path) {
+        let path = dir
+            .join(session.id().to_string())
+            .with_extension(CAPTURE_EXTENSION);
+
+        match CaptureWriter::create(&path, CaptureSide::Server) {
+            Ok(capture) => {
+                tracing::debug!("Capturing session {} to {}", session.id(), path.display());
+                session.set_capture(capture);
+            }
+            Err(e) => tracing::warn!("Failed to create capture {}: {}", path.display(), e),
+        }
+    }
+
+    /// runs the TLS and WebSocket handshakes, both have to be done within the handshake timeout
+    async fn accept<S: Connection>(
+        &self,
+        stream: S,
+        endpoint: &Endpoint,
+    ) -> io::Result<BoxedConnection> {
+        match future::timeout(HANDSHAKE_TIMEOUT, self.upgrade(stream, endpoint)).await {
+            Ok(transport) => transport,
+            Err(e) => Err(io::Error::new(io::ErrorKind::TimedOut, e)),
+        }
+    }
+
+    #[cfg_attr(not(any(feature = "tls", feature = "ws")), allow(unused_variables))]
+    async fn upgrade<S: Connection>(
+        &self,
+        stream: S,
+        endpoint: &Endpoint,
+    ) -> io::Result<BoxedConnection> {
+        #[cfg(feature = "tls")]
+        let stream: BoxedConnection = match &self.tls {
+            Some(acceptor) if endpoint.is_tls() => Box::new(acceptor.accept(stream).await?),
+            _ => Box::new(stream),
+        };
+        #[cfg(not(feature = "tls"))]
+        let stream: BoxedConnection = Box::new(stream);
+
+        #[cfg(feature = "ws")]
+        if endpoint.is_websocket() {
+            return Ok(Box::new(WsStream::accept(stream, DECODE_LIMITS).await?));
+        }
+
+        Ok(stream)
+    }
+
+    /// decoder with the limits the server accepts for each message type
+    fn decoder() -> MessageDecoder {
+        MessageDecoder::new()
+            .with_limits(DECODE_LIMITS)
+            .with_type_limits(MessageType::Hello, HANDSHAKE_DECODE_LIMITS)
+            .with_type_limits(MessageType::Ping, KEEPALIVE_DECODE_LIMITS)
+            .with_type_limits(MessageType::Pong, KEEPALIVE_DECODE_LIMITS)
+            .with_type_limits(MessageType::Disconnect, DISCONNECT_DECODE_LIMITS)
+            .with_type_limits(MessageType::Login, AUTH_DECODE_LIMITS)
+            .with_type_limits(MessageType::Register, AUTH_DECODE_LIMITS)
+    }
+
+    /// reader task of a session, handles the messages of the client until the session ends
+    async fn handle_connection(
+        session: Session,
+        mut reader: SessionReader,
+        appdata: Arc<RwLock<AppData>>,
+    ) {
+        let addr = match session.peer_addr() {
+            Ok(addr) => addr,
+            Err(e) => {
+                tracing::error!("Failed to get peer address: {}", e);
+                return;
+            }
+        };
+        tracing::info!("New connection from {}", addr);
+
+        match Self::handshake(&session, &mut reader).await {
+            Ok(welcome) => tracing::info!(
+                "Handshake with {} done | protocol v{}, capabilities {:?}",
+                addr,
+                welcome.protocol_version(),
+                welcome.capabilities()
+            ),
+            Err(e) => {
+                tracing::error!("Handshake with {} failed: {}", addr, e);
+                session.close();
+                if let Err(e) = session.shutdown().await {
+                    tracing::error!("Failed to close connection from {}: {}", addr, e);
+                }
+                return;
+            }
         }
 
         appdata
             .write()
             .await
-            .insert_session(session.read().await.id(), session.clone());
+            .insert_session(session.id(), session.clone());
 
-        let addr = session.read().await.peer_addr().unwrap().clone();
-        tracing::info!("New connection from {}", addr);
+        let mut next_check = Instant::now() + HEALTH_CHECK_INTERVAL;
+        while !session.closed() {
+            // the decoder keeps the bytes that were read when the wait for the health check ends
+            let wait = next_check.saturating_duration_since(Instant::now());
+            let received = future::timeout(wait, reader.recv()).await;
 
-        while !session.read().await.closed() {
-            if !session.write().await.recieved_msg().await {
-                continue;
+            if Instant::now() >= next_check {
+                next_check = Instant::now() + HEALTH_CHECK_INTERVAL;
+
+                if !Self::health_check(&session, &addr).await {
+                    let detail = format!("No Pong within {:?}", PONG_TIMEOUT);
+                    let disconnect = Disconnect::new(DisconnectReason::IdleTimeout, detail);
+                    Self::disconnect(&session, &mut reader, disconnect).await;
+                    break;
+                }
             }
 
-            let msg = match session.write().await.recv().await {
+            let Ok(received) = received else {
+                continue;
+            };
+
+            let msg = match received {
                 Ok(msg) => msg,
+                Err(MessageError::ConnectionClosed) => {
+                    session.close();
+                    break;
+                }
+                Err(e @ (MessageError::ChecksumMismatch(_, _) | MessageError::MissingChecksum)) => {
+                    tracing::warn!("Dropping corrupted frame from {}: {}", addr, e);
+                    continue;
+                }
+                Err(MessageError::UnknownType(code)) => {
+                    tracing::warn!(
+                        "Ignoring message with unknown type {:#x} from {}",
+                        code,
+                        addr
+                    );
+                    continue;
+                }
+                Err(e) if reader.checksums() && e.is_frame_error() => {
+                    // the decoder skipped the frame and resyncs on the next header
+                    tracing::warn!("Dropping corrupted frame from {}: {}", addr, e);
+                    continue;
+                }
                 Err(e) => {
                     tracing::error!("Failed to receive message from {}: {}", addr, e);
-                    session.write().await.close();
+                    // the connection is dropped anyway, so a failed reply does not matter
+                    let _ = session.send(ErrorReply::from(&e).to_message()).await;
+
+                    let disconnect =
+                        Disconnect::new(DisconnectReason::ProtocolError, e.to_string());
+                    Self::disconnect(&session, &mut reader, disconnect).await;
                     break;
                 }
             };
 
             handle_message(session.clone(), appdata.clone(), &msg).await;
 
-            println!("{:#?}", appdata.read().await);
+            // a client that keeps sending never lets the read return pending, the writer task
+            // and the other sessions get their turn here
+            task::yield_now().await;
         }
 
-        tracing::info!("Connection from {} closed", addr);
-        if let Err(e) = session.write().await.shutdown() {
+        appdata.write().await.end_session(&session);
+
+        if let Err(e) = session.shutdown().await {
             tracing::error!("Failed to close connection from {}: {}", addr, e);
         }
 
-        println!("{:#?}", appdata.read().await);
+        tracing::info!(
+            "Connection from {} closed | last round trip {:?}, {:?}",
+            addr,
+            session.rtt(),
+            session.queue_metrics()
+        );
     }
 
-    async fn health_check(session: Arc<RwLock<Session>>, appdata: Arc<RwLock<AppData>>) {
-        loop {
-            task::sleep(HEALTH_CHECK_INTERVAL).await;
+    /// expects a Hello as first frame and answers with the negotiated Welcome, incompatible
+    /// clients get an Error frame instead
+    async fn handshake(
+        session: &Session,
+        reader: &mut SessionReader,
+    ) -> Result<Welcome, MessageError> {
+        let hello = match future::timeout(HANDSHAKE_TIMEOUT, reader.recv()).await {
+            Ok(hello) => hello,
+            Err(e) => Err(MessageError::ReadError(io::Error::new(
+                io::ErrorKind::TimedOut,
+                e,
+            ))),
+        };
+
+        let welcome = hello
+            .and_then(|msg| Hello::from_message(&msg))
+            .and_then(|hello| hello.negotiate(SOFTWARE_VERSION, CAPABILITIES));
 
-            if session.read().await.closed() {
-                break;
+        match welcome {
+            Ok(welcome) => {
+                session.send(welcome.to_message()).await?;
+                session.set_capabilities(welcome.capabilities());
+                session.set_protocol_version(welcome.protocol_version());
+                reader.set_capabilities(welcome.capabilities());
+                Ok(welcome)
+            }
+            Err(e) => {
+                // the connection is dropped anyway, so a failed reply does not matter
+                let _ = session.send(ErrorReply::from(&e).to_message()).await;
+                Err(e)
             }
+        }
+    }
 
-            if !session.write().await.health_check().await {
-                tracing::error!(
-                    "Health check failed for {}",
-                    session.read().await.peer_addr().unwrap()
-                );
+    /// tells the client why the connection ends and gives it a moment to acknowledge it, frames
+    /// that were already on their way are dropped
+    async fn disconnect(session: &Session, reader: &mut SessionReader, disconnect: Disconnect) {
+        session.close();
 
-                handle_message(
-                    session.clone(),
-                    appdata.clone(),
-                    &Message::DISCONNECT_MESSAGE,
-                )
-                .await;
+        if let Err(e) = session.send(disconnect.to_message()).await {
+            tracing::debug!("Failed to send Disconnect | {}", e);
+            return;
+        }
 
-                break;
+        let ack = async {
+            loop {
+                match reader.recv().await {
+                    Ok(msg) if msg.mtype() == MessageType::Disconnect => return true,
+                    Err(MessageError::ConnectionClosed | MessageError::ReadError(_)) => {
+                        return false
+                    }
+                    _ => {}
+                }
             }
+        };
+
+        if !future::timeout(DISCONNECT_TIMEOUT, ack)
+            .await
+            .unwrap_or(false)
+        {
+            tracing::debug!(
+                "Disconnect of session {} was not acknowledged",
+                session.id()
+            );
+        }
+    }
+
+    /// sends the next Ping, false if the client did not answer the previous ones in time
+    async fn health_check(session: &Session, addr: &PeerAddr) -> bool {
+        if session.ping_timed_out() {
+            tracing::error!("No Pong from {} within {:?}", addr, PONG_TIMEOUT);
+            return false;
+        }
+
+        if let Err(e) = session.ping().await {
+            tracing::error!("Health check failed for {}: {}", addr, e);
+            return false;
         }
+
+        tracing::debug!("Outbound queue of {} | {:?}", addr, session.queue_metrics());
+
+        true
     }
 }
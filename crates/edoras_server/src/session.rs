@@ -1,77 +1,359 @@
-use async_std::net::TcpStream;
-use edoras_core::{Message, MessageBuilder, MessageError, MessageType};
+use crate::application::{FLUSH_TIMEOUT, PONG_TIMEOUT};
+use crate::transport::{BoxedConnection, Connection, PeerAddr, Socket};
+use async_std::future;
+use async_std::task::{self, JoinHandle};
+use edoras_core::{
+    Capabilities, CaptureWriter, Compression, Direction, Disconnect, DisconnectReason, Encoding,
+    Frame, Keepalive, Message, MessageDecoder, MessageEncoder, MessageError, OutboundQueue,
+    Payload, Pong, ProtocolVersion, QueueError, QueueMetrics,
+};
+use futures::io::{ReadHalf, WriteHalf};
+use futures::{AsyncReadExt, AsyncWriteExt};
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 use uuid::Uuid;
 
-#[derive(Debug)]
+/// handle of the connection of a client with everything the server knows about it
+///
+/// a session runs as two tasks: the connection task owns the [`SessionReader`] and handles the
+/// messages of the client, the writer task writes the frames that were queued through any clone
+/// of the handle. The state is only locked for plain reads and updates, never across socket I/O
+#[derive(Debug, Clone)]
 pub(crate) struct Session {
     id: Uuid,
-    stream: TcpStream,
+    socket: Socket,
+    outbound: OutboundQueue,
+    capture: SessionCapture,
+    state: Arc<Mutex<SessionState>>,
+}
+
+/// reading side of a session, owned by its connection task
+#[derive(Debug)]
+pub(crate) struct SessionReader<S = BoxedConnection> {
+    id: Uuid,
+    reader: ReadHalf<S>,
+    decoder: MessageDecoder,
+    capture: SessionCapture,
+}
+
+#[derive(Debug)]
+struct SessionState {
+    encoder: MessageEncoder,
     closed: bool,
+    keepalive: Keepalive,
+    writer: Option<JoinHandle<()>>,
 
     user: Option<String>,
 }
 
+/// capture of a session, shared by its reader and its writer task
+#[derive(Debug, Clone, Default)]
+struct SessionCapture(Arc<Mutex<Option<CaptureWriter<BufWriter<File>>>>>);
+
 impl Session {
-    pub fn new(stream: TcpStream) -> Self {
-        Self {
-            id: Uuid::new_v4(),
-            stream,
-            closed: false,
+    /// splits the stream and starts the writer task that drains the outbound queue, the reader
+    /// goes to the task that handles the connection
+    pub fn new<S: Connection>(
+        stream: S,
+        decoder: MessageDecoder,
+        outbound: OutboundQueue,
+    ) -> (Self, SessionReader<S>) {
+        let id = Uuid::new_v4();
+        let socket = stream.socket();
+        let (reader, writer) = stream.split();
+        let capture = SessionCapture::default();
+        let writer = task::spawn(write_frames(id, writer, outbound.clone(), capture.clone()));
 
-            user: None,
-        }
+        let session = Self {
+            id,
+            socket,
+            outbound,
+            capture: capture.clone(),
+            state: Arc::new(Mutex::new(SessionState {
+                encoder: MessageEncoder::new(),
+                closed: false,
+                keepalive: Keepalive::new(PONG_TIMEOUT),
+                writer: Some(writer),
+
+                user: None,
+            })),
+        };
+        let reader = SessionReader {
+            id,
+            reader,
+            decoder,
+            capture,
+        };
+
+        (session, reader)
     }
 
     pub fn id(&self) -> Uuid {
         self.id
     }
 
-    pub fn user(&self) -> Option<&String> {
-        self.user.as_ref()
+    pub fn user(&self) -> Option<String> {
+        self.state().user.clone()
     }
 
-    pub fn set_user(&mut self, user: String) {
-        if self.user.is_some() {
+    pub fn set_user(&self, user: String) {
+        let mut state = self.state();
+        if state.user.is_some() {
             return;
         }
-        self.user = Some(user);
+        state.user = Some(user);
     }
 
-    pub fn remove_user(&mut self) {
-        self.user = None;
+    pub fn remove_user(&self) {
+        self.state().user = None;
     }
 
-    pub fn peer_addr(&self) -> std::io::Result<std::net::SocketAddr> {
-        self.stream.peer_addr()
+    pub fn peer_addr(&self) -> std::io::Result<PeerAddr> {
+        self.socket.peer_addr()
     }
 
+    /// true once the session was closed or its outbound queue can not take frames anymore
     pub fn closed(&self) -> bool {
-        self.closed
+        self.state().closed || self.outbound.is_closed()
+    }
+
+    pub fn close(&self) {
+        self.state().closed = true;
+    }
+
+    /// closes the outbound queue, gives the writer task a moment to write the frames that are
+    /// left and shuts the socket down
+    pub async fn shutdown(&self) -> std::io::Result<()> {
+        self.outbound.close();
+        let writer = self.state().writer.take();
+        if let Some(writer) = writer {
+            if future::timeout(FLUSH_TIMEOUT, writer).await.is_err() {
+                tracing::debug!(
+                    "Dropping {} queued frames of session {}",
+                    self.outbound.len(),
+                    self.id
+                );
+            }
+        }
+
+        self.socket.shutdown()
+    }
+
+    /// records every frame that is sent or received from now on
+    pub fn set_capture(&self, capture: CaptureWriter<BufWriter<File>>) {
+        self.capture.set(capture);
+    }
+
+    /// depth and counters of the outbound queue
+    pub fn queue_metrics(&self) -> QueueMetrics {
+        self.outbound.metrics()
+    }
+
+    /// applies the negotiated frame options to every following frame that is sent, the reader
+    /// applies them to the received ones
+    pub fn set_capabilities(&self, capabilities: Capabilities) {
+        let mut state = self.state();
+        state
+            .encoder
+            .set_checksum(capabilities.contains(Capabilities::CHECKSUM));
+        state
+            .encoder
+            .set_channels(capabilities.contains(Capabilities::CHANNELS));
+        state
+            .encoder
+            .set_compression(Compression::from_capabilities(capabilities));
+    }
+
+    /// writes every following frame in the encoding of the negotiated protocol version
+    pub fn set_protocol_version(&self, version: ProtocolVersion) {
+        self.state()
+            .encoder
+            .set_encoding(Encoding::from_version(version));
+    }
+
+    pub async fn send(&self, message: Message) -> Result<(), QueueError> {
+        let frame = self.encoder().encode(&message);
+        self.send_frame(&message, frame).await
+    }
+
+    pub fn encoder(&self) -> MessageEncoder {
+        self.state().encoder
+    }
+
+    /// queues a frame of the message that was encoded for this sessions encoder, e.g. one that
+    /// is shared with other sessions. A client that is too slow to keep up with its queue is
+    /// disconnected if the overflow policy says so
+    ///
+    /// a push that waits for room gives up after the pong timeout, a client that does not read
+    /// for that long is considered dead like one that does not answer a Ping
+    pub async fn send_frame(&self, message: &Message, frame: Frame) -> Result<(), QueueError> {
+        let result = future::timeout(PONG_TIMEOUT, self.outbound.push(message, frame))
+            .await
+            .unwrap_or(Err(QueueError::Overflow));
+        if result == Err(QueueError::Overflow) {
+            tracing::warn!(
+                "Outbound queue of session {} overflowed | {:?}",
+                self.id,
+                self.outbound.metrics()
+            );
+
+            // a Disconnect always fits into the queue and is written before the messages that
+            // are queued on any channel
+            let detail = format!("More than {} frames queued", self.outbound.capacity());
+            let disconnect = Disconnect::new(DisconnectReason::SlowConsumer, detail).to_message();
+            let _ = self
+                .outbound
+                .push(&disconnect, self.encoder().encode(&disconnect))
+                .await;
+            self.outbound.close();
+        }
+
+        result
     }
 
-    pub fn close(&mut self) {
-        self.closed = true;
+    /// sends a Ping that has to be answered within the pong timeout
+    pub async fn ping(&self) -> Result<(), QueueError> {
+        let ping = self.state().keepalive.ping();
+        self.send(ping.to_message()).await
     }
 
-    pub fn shutdown(&self) -> std::io::Result<()> {
-        self.stream.shutdown(std::net::Shutdown::Both)
+    /// round trip time of the Ping the Pong answers, `None` for unknown pings
+    pub fn pong(&self, pong: &Pong) -> Option<Duration> {
+        self.state().keepalive.pong(pong)
     }
 
-    pub async fn recieved_msg(&mut self) -> bool {
-        Message::peek_for_header(&mut self.stream).await
+    /// true if a Ping was not answered in time, the client is considered dead then
+    pub fn ping_timed_out(&self) -> bool {
+        self.state().keepalive.timed_out()
     }
 
-    pub async fn send(&mut self, message: Message) -> Result<(), MessageError> {
-        message.send(&mut self.stream).await
+    /// round trip time of the last answered Ping
+    pub fn rtt(&self) -> Option<Duration> {
+        self.state().keepalive.rtt()
     }
 
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<S: Connection> SessionReader<S> {
+    /// checks every following frame that is received with the negotiated frame options
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.decoder
+            .set_require_checksum(capabilities.contains(Capabilities::CHECKSUM));
+        self.decoder
+            .set_compression(Compression::from_capabilities(capabilities));
+    }
+
+    /// true once checksums were negotiated, broken frames are then considered corrupted
+    pub fn checksums(&self) -> bool {
+        self.decoder.require_checksum()
+    }
+
+    /// next message of the client, the bytes that were read are kept if the future is dropped
     pub async fn recv(&mut self) -> Result<Message, MessageError> {
-        Message::recv(&mut self.stream).await
+        let message = self.decoder.recv(&mut self.reader).await;
+        if let Some(frame) = self.decoder.take_last_frame() {
+            self.capture.record(self.id, Direction::Incoming, &frame);
+        }
+        message
+    }
+}
+
+impl SessionCapture {
+    fn set(&self, capture: CaptureWriter<BufWriter<File>>) {
+        *self.lock() = Some(capture);
+    }
+
+    fn is_active(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    /// a capture that fails to write is dropped, the session goes on without it
+    fn record(&self, session: Uuid, direction: Direction, frame: &[u8]) {
+        let mut capture = self.lock();
+        let Some(writer) = capture.as_mut() else {
+            return;
+        };
+
+        if let Err(e) = writer.record(direction, frame) {
+            tracing::warn!("Failed to capture frame of session {}: {}", session, e);
+            *capture = None;
+        }
     }
 
-    pub async fn health_check(&mut self) -> bool {
-        self.send(MessageBuilder::new().with_type(MessageType::Ping).build())
+    fn lock(&self) -> MutexGuard<'_, Option<CaptureWriter<BufWriter<File>>>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// writer task of a session, writes the queued frames until the queue is closed and drained.
+/// A failed write closes the queue, so the session ends
+async fn write_frames<S: Connection>(
+    session: Uuid,
+    mut writer: WriteHalf<S>,
+    outbound: OutboundQueue,
+    capture: SessionCapture,
+) {
+    while let Some(frame) = outbound.pop().await {
+        if capture.is_active() {
+            capture.record(session, Direction::Outgoing, &frame.to_bytes());
+        }
+
+        if let Err(e) = frame.send(&mut writer).await {
+            tracing::debug!("Failed to write to session {} | {}", session, e);
+            outbound.close();
+            return;
+        }
+    }
+
+    let _ = writer.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::{TcpListener, TcpStream};
+    use edoras_core::{MessageBuilder, MessageType, OverflowPolicy};
+    use std::time::Instant;
+
+    #[async_std::test]
+    async fn blocked_session_closes_after_pong_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // the peer never reads, so the writer gets stuck once the socket buffers are full
+        let _peer = TcpStream::connect(listener.local_addr().unwrap())
             .await
-            .is_ok()
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let outbound = OutboundQueue::new(1, OverflowPolicy::Block);
+        let (session, _reader) = Session::new(stream, MessageDecoder::new(), outbound);
+        let data = MessageBuilder::new()
+            .with_type(MessageType::extension(0x80).unwrap())
+            .with_field(vec![0; 64 * 1024])
+            .build();
+
+        while let Ok(result) =
+            future::timeout(Duration::from_millis(200), session.send(data.clone())).await
+        {
+            assert_eq!(result, Ok(()));
+        }
+
+        // the health check still gets its Ping into the full queue
+        let start = Instant::now();
+        let ping = future::timeout(Duration::from_secs(1), session.ping()).await;
+        assert_eq!(ping, Ok(Ok(())));
+
+        assert_eq!(session.send(data).await, Err(QueueError::Overflow));
+        assert!(start.elapsed() >= PONG_TIMEOUT);
+        assert!(session.ping_timed_out());
+        assert!(session.closed());
     }
 }
@@ -0,0 +1,110 @@
+use async_std::net::TcpStream;
+#[cfg(unix)]
+use async_std::os::unix::net::UnixStream;
+#[cfg(feature = "tls")]
+use edoras_core::tls::server::TlsStream;
+#[cfg(feature = "ws")]
+use edoras_core::WsStream;
+use futures::{AsyncRead, AsyncWrite};
+use std::fmt::{self, Debug, Display};
+use std::io;
+use std::net::{Shutdown, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// a stream a session can run on
+pub(crate) trait Connection:
+    AsyncRead + AsyncWrite + Unpin + Send + Sync + Debug + 'static
+{
+    /// socket underneath the stream, it stays usable after the stream was split
+    fn socket(&self) -> Socket;
+}
+
+/// connection of a session, the listeners and handshakes decide which stream it actually is
+pub(crate) type BoxedConnection = Box<dyn Connection>;
+
+/// handle of the socket of a connection
+#[derive(Debug, Clone)]
+pub(crate) enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// address of the other side of a connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PeerAddr {
+    Tcp(SocketAddr),
+    /// clients of unix sockets usually have no path
+    #[cfg(unix)]
+    Unix(Option<PathBuf>),
+}
+
+// IMPLEMENTATION
+
+impl Socket {
+    pub fn peer_addr(&self) -> io::Result<PeerAddr> {
+        match self {
+            Self::Tcp(stream) => stream.peer_addr().map(PeerAddr::Tcp),
+            #[cfg(unix)]
+            Self::Unix(stream) => {
+                let addr = stream.peer_addr()?;
+                Ok(PeerAddr::Unix(addr.as_pathname().map(PathBuf::from)))
+            }
+        }
+    }
+
+    /// shuts both directions down, pending reads and writes of the stream fail then
+    pub fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Self::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            Self::Unix(None) => write!(f, "unix socket"),
+        }
+    }
+}
+
+impl Connection for TcpStream {
+    fn socket(&self) -> Socket {
+        Socket::Tcp(self.clone())
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn socket(&self) -> Socket {
+        Socket::Unix(self.clone())
+    }
+}
+
+#[cfg(feature = "tls")]
+impl<S: Connection> Connection for TlsStream<S> {
+    fn socket(&self) -> Socket {
+        self.get_ref().0.socket()
+    }
+}
+
+#[cfg(feature = "ws")]
+impl<S: Connection> Connection for WsStream<S> {
+    fn socket(&self) -> Socket {
+        self.get_ref().socket()
+    }
+}
+
+impl Connection for BoxedConnection {
+    fn socket(&self) -> Socket {
+        self.as_ref().socket()
+    }
+}
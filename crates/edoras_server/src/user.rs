@@ -1,5 +1,6 @@
 use uuid::Uuid;
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub(crate) struct User {
     username: String,
@@ -7,6 +8,7 @@ pub(crate) struct User {
     session_id: Option<Uuid>,
 }
 
+#[allow(dead_code)]
 impl User {
     pub fn new(username: String) -> Self {
         Self {